@@ -0,0 +1,311 @@
+// Computer opponent: depth-limited negamax with alpha-beta pruning
+
+use std::{sync::{mpsc::{self, Receiver, Sender}, Arc}, thread};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{bitboard::{column_mask, Position, HEIGHT, WIDTH}, book::Book, solver::Solver};
+
+// Centre columns take part in more lines, so search them first
+pub const COLUMN_ORDER: [usize; WIDTH] = [3, 2, 4, 1, 5, 0, 6];
+
+const WIN: i32 = 1_000_000;
+const THREAT: i32 = 100;
+
+// Solving earlier positions takes minutes, so without a book covering them
+// Perfect plays like Expert until then
+const SOLVER_MIN_MOVES: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+    // Uses the solver instead of the heuristic search
+    Perfect
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 5] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard, Difficulty::Expert, Difficulty::Perfect];
+
+    pub fn depth(self) -> u32 {
+        match self {
+            Difficulty::Easy => 2,
+            Difficulty::Medium => 4,
+            Difficulty::Hard => 7,
+            Difficulty::Expert | Difficulty::Perfect => 10
+        }
+    }
+
+    // Chance of deliberately playing a suboptimal move
+    pub fn blunder_rate(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.15,
+            Difficulty::Hard => 0.05,
+            Difficulty::Expert | Difficulty::Perfect => 0.
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Ai {
+    difficulty: Difficulty,
+    rng: StdRng,
+    book: Option<Arc<Book>>,
+    // Created on first use, the transposition table is large
+    solver: Option<Solver>
+}
+
+impl Ai {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self { difficulty, rng: StdRng::from_entropy(), book: None, solver: None }
+    }
+
+    pub fn with_seed(difficulty: Difficulty, seed: u64) -> Self {
+        Self { difficulty, rng: StdRng::seed_from_u64(seed), book: None, solver: None }
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty;
+    }
+
+    pub fn set_book(&mut self, book: Option<Arc<Book>>) {
+        if let Some(solver) = &mut self.solver {
+            solver.set_book(book.clone());
+        }
+        self.book = book;
+    }
+
+    // Whether scores for the position are the solver's exact ones rather than heuristic
+    pub fn use_solver(&self, pos: &Position) -> bool {
+        let in_book = self.book.as_ref().is_some_and(|book| pos.moves() < book.plies());
+        self.difficulty == Difficulty::Perfect && (in_book || pos.moves() >= SOLVER_MIN_MOVES)
+    }
+
+    pub fn choose_move(&mut self, pos: &Position) -> Option<usize> {
+        self.choose_scored_move(pos).map(|(col, _)| col)
+    }
+
+    // The chosen move with its score
+    pub fn choose_scored_move(&mut self, pos: &Position) -> Option<(usize, i32)> {
+        let scores = self.score_moves(pos);
+        let best = scores.iter().map(|&(_, score)| score).max()?;
+
+        let (good, bad): (Vec<_>, Vec<_>) = scores.into_iter().partition(|&(_, score)| score == best);
+        let candidates = if !bad.is_empty() && self.rng.gen_bool(self.difficulty.blunder_rate()) {
+            bad
+        } else {
+            good
+        };
+
+        candidates.choose(&mut self.rng).copied()
+    }
+
+    // Score of every legal move from the point of view of the player to move
+    pub fn score_moves(&mut self, pos: &Position) -> Vec<(usize, i32)> {
+        if self.use_solver(pos) {
+            let book = self.book.clone();
+            let solver = self.solver.get_or_insert_with(|| {
+                let mut solver = Solver::new();
+                solver.set_book(book);
+                solver
+            });
+            return solver.analyze(pos).into_iter()
+                .enumerate()
+                .filter_map(|(col, score)| Some((col, score?)))
+                .collect();
+        }
+
+        let depth = self.difficulty.depth();
+        COLUMN_ORDER.into_iter()
+            .filter(|&col| pos.can_play(col))
+            .map(|col| {
+                let score = if pos.is_winning_move(col) {
+                    WIN - pos.moves() as i32
+                } else {
+                    let mut next = *pos;
+                    next.play(col);
+                    -negamax(&next, depth.saturating_sub(1), -WIN, WIN)
+                };
+                (col, score)
+            })
+            .collect()
+    }
+}
+
+// An AI answering positions on a thread of its own, with `work` giving the answer for each
+#[derive(Debug)]
+pub struct Worker<T> {
+    requests: Sender<Position>,
+    results: Receiver<(Position, T)>
+}
+
+impl<T: Send + 'static> Worker<T> {
+    pub fn spawn(mut ai: Ai, mut work: impl FnMut(&mut Ai, &Position) -> T + Send + 'static) -> Self {
+        let (requests, positions) = mpsc::channel::<Position>();
+        let (answers, results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut pos) = positions.recv() {
+                // Only the latest position is still wanted
+                while let Ok(next) = positions.try_recv() {
+                    pos = next;
+                }
+                if answers.send((pos, work(&mut ai, &pos))).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { requests, results }
+    }
+
+    pub fn request(&self, pos: Position) {
+        let _ = self.requests.send(pos);
+    }
+
+    // The next answer with the position it is for, if one has come in
+    pub fn try_result(&self) -> Option<(Position, T)> {
+        self.results.try_recv().ok()
+    }
+}
+
+fn negamax(pos: &Position, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+    if pos.can_win_next() {
+        return WIN - pos.moves() as i32;
+    }
+    if pos.moves() as usize >= HEIGHT * WIDTH - 1 {
+        return 0;
+    }
+
+    let next = pos.non_losing_moves();
+    if next == 0 {
+        // Every move lets the opponent win straight away
+        return -(WIN - pos.moves() as i32 - 1);
+    }
+    if depth == 0 {
+        return evaluate(pos);
+    }
+
+    let mut best = -WIN;
+    for col in COLUMN_ORDER {
+        if next & column_mask(col) == 0 {
+            continue;
+        }
+        let mut child = *pos;
+        child.play(col);
+        let score = -negamax(&child, depth - 1, -beta, -alpha);
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+// Static evaluation: open threes (cells that would complete four) and centre control
+fn evaluate(pos: &Position) -> i32 {
+    let threats = pos.winning_position().count_ones() as i32 - pos.opponent_winning_position().count_ones() as i32;
+
+    let current = pos.current_mask();
+    let opponent = current ^ pos.mask();
+    let centre = column_mask(WIDTH / 2);
+    let centre_score = (current & centre).count_ones() as i32 - (opponent & centre).count_ones() as i32;
+
+    threats * THREAT + centre_score * 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(cols: &[usize]) -> Position {
+        let mut pos = Position::new();
+        for &col in cols {
+            pos.play(col);
+        }
+        pos
+    }
+
+    fn best_moves(ai: &mut Ai, pos: &Position) -> Vec<usize> {
+        let scores = ai.score_moves(pos);
+        let best = scores.iter().map(|&(_, score)| score).max().unwrap();
+        scores.into_iter().filter(|&(_, score)| score == best).map(|(col, _)| col).collect()
+    }
+
+    #[test]
+    fn takes_a_win() {
+        // Red completes the bottom row
+        let pos = position(&[0, 0, 1, 1, 2, 2]);
+        for difficulty in Difficulty::ALL {
+            assert_eq!(best_moves(&mut Ai::with_seed(difficulty, 1), &pos), vec![3], "{difficulty:?}");
+        }
+    }
+
+    #[test]
+    fn blocks_a_loss() {
+        // Yellow has to stop the bottom row
+        let pos = position(&[0, 6, 1, 6, 2]);
+        for difficulty in Difficulty::ALL {
+            assert_eq!(best_moves(&mut Ai::with_seed(difficulty, 1), &pos), vec![3], "{difficulty:?}");
+        }
+    }
+
+    #[test]
+    fn expert_never_blunders() {
+        assert_eq!(Difficulty::Expert.blunder_rate(), 0.);
+        let pos = position(&[0, 6, 1, 6, 2]);
+        for seed in 0..30 {
+            assert_eq!(Ai::with_seed(Difficulty::Expert, seed).choose_move(&pos), Some(3));
+        }
+        // Easy does now and then
+        assert!((0..100).any(|seed| Ai::with_seed(Difficulty::Easy, seed).choose_move(&pos) != Some(3)));
+    }
+
+    #[test]
+    fn perfect_uses_the_solver_late_in_the_game() {
+        let moves = [0, 1, 2, 3, 4, 5, 6, 0, 1, 2, 3, 4];
+        let early = position(&moves[..SOLVER_MIN_MOVES as usize - 1]);
+        let late = position(&moves[..SOLVER_MIN_MOVES as usize]);
+        let mut perfect = Ai::with_seed(Difficulty::Perfect, 1);
+        assert!(!perfect.use_solver(&early));
+        assert!(perfect.use_solver(&late));
+        assert!(!Ai::with_seed(Difficulty::Expert, 1).use_solver(&late));
+
+        let exact: Vec<_> = Solver::new().analyze(&late).into_iter()
+            .enumerate()
+            .filter_map(|(col, score)| Some((col, score?)))
+            .collect();
+        assert_eq!(perfect.score_moves(&late), exact);
+    }
+
+    #[test]
+    fn perfect_uses_the_book_early() {
+        let mut perfect = Ai::with_seed(Difficulty::Perfect, 1);
+        let book = Book::read(&b"C4BK\x01\x07\x06\x04\x00\x00\x00\x00"[..]).unwrap();
+        perfect.set_book(Some(Arc::new(book)));
+        assert!(perfect.use_solver(&position(&[3, 3, 3])));
+        assert!(!perfect.use_solver(&position(&[3, 3, 3, 3])));
+    }
+
+    #[test]
+    fn worker_answers_the_latest_position() {
+        let worker = Worker::spawn(Ai::with_seed(Difficulty::Easy, 1), |_, pos| pos.moves());
+        let latest = position(&[3, 3, 3]);
+        for moves in [&[][..], &[3], &[3, 3], &[3, 3, 3]] {
+            worker.request(position(moves));
+        }
+        let answer = loop {
+            match worker.try_result() {
+                Some((pos, moves)) if pos == latest => break moves,
+                _ => thread::yield_now()
+            }
+        };
+        assert_eq!(answer, 3);
+    }
+}
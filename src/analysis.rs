@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use c4::{ai::{Ai, Difficulty, Worker}, bitboard::{Position, WIDTH}, book::Book, engine::Score, solver::Evaluation};
+
+// Evaluations of playing in each column, worked out on their own thread since the solver
+// can take seconds
+#[derive(Debug)]
+pub struct Analysis {
+    worker: Worker<[Option<Score>; WIDTH]>,
+    requested: Option<Position>,
+    scores: Option<(Position, [Option<Score>; WIDTH])>
+}
+
+impl Analysis {
+    pub fn new(book: Option<Arc<Book>>) -> Self {
+        let mut ai = Ai::new(Difficulty::Perfect);
+        ai.set_book(book);
+        let worker = Worker::spawn(ai, |ai, pos| {
+            let exact = ai.use_solver(pos);
+            let mut scores = [None; WIDTH];
+            for (col, score) in ai.score_moves(pos) {
+                scores[col] = Some(if exact {
+                    Score::Exact(Evaluation::from_score(pos, score))
+                } else {
+                    Score::Heuristic(score)
+                });
+            }
+            scores
+        });
+        Self { worker, requested: None, scores: None }
+    }
+
+    // The scores for the position, asking for them if they are not known yet
+    pub fn scores(&mut self, pos: &Position) -> Option<[Option<Score>; WIDTH]> {
+        while let Some(result) = self.worker.try_result() {
+            self.scores = Some(result);
+        }
+        if let Some((_, scores)) = self.scores.filter(|(scored, _)| scored == pos) {
+            return Some(scores);
+        }
+        if self.requested != Some(*pos) {
+            self.worker.request(*pos);
+            self.requested = Some(*pos);
+        }
+        None
+    }
+}
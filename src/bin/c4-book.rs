@@ -0,0 +1,35 @@
+// Generates an opening book for the solver
+//
+// Usage: c4-book <plies> <output> [threads]
+
+use std::{env, thread};
+
+use anyhow::{bail, Context};
+use c4::{bitboard::{HEIGHT, WIDTH}, book::Book};
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        bail!("usage: {} <plies> <output> [threads]", args[0]);
+    }
+
+    let plies: u32 = args[1].parse().context("invalid number of plies")?;
+    // The header has a byte for it, and no game is longer
+    if plies as usize > WIDTH * HEIGHT {
+        bail!("a book cannot go deeper than {} plies", WIDTH * HEIGHT);
+    }
+    let threads = match args.get(3) {
+        Some(threads) => threads.parse().context("invalid number of threads")?,
+        None => thread::available_parallelism().map_or(1, |n| n.get())
+    };
+
+    let book = Book::generate(plies, threads, |done, total| {
+        if done % 1000 == 0 || done == total {
+            eprintln!("{done}/{total}");
+        }
+    });
+    book.save(&args[2])?;
+    println!("Wrote {} positions to {}", book.len(), args[2]);
+
+    Ok(())
+}
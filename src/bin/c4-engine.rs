@@ -0,0 +1,136 @@
+// The built-in AI as an engine speaking the protocol in c4::engine on stdin and stdout
+//
+// Usage: c4-engine [easy|medium|hard|expert|perfect] [book]
+//
+// Standard games are searched by the AI at the given difficulty, Medium by default, and
+// the search limits in "go" are ignored. Other rules are played from the game rules
+// alone: a move that wins straight away if there is one, otherwise a random move that
+// does not let the next player win straight away, as far as the move time or a share of
+// the clock allows checking. Positions that cannot be played, because they are not
+// understood or the game is over, are answered with "bestmove none".
+
+use std::{env, io::{self, BufRead, Write}, sync::Arc, time::Instant};
+
+use anyhow::{bail, Context};
+use c4::{ai::{Ai, Difficulty}, bitboard::Position, book::Book, engine::{Command, Info, Limits, Response, Score}, game::{Game, Move, Scoring}, solver::Evaluation};
+use rand::seq::SliceRandom;
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 3 {
+        bail!("usage: {} [easy|medium|hard|expert|perfect] [book]", args[0]);
+    }
+
+    let difficulty = match args.get(1) {
+        Some(name) => *Difficulty::ALL.iter()
+            .find(|difficulty| format!("{difficulty:?}").eq_ignore_ascii_case(name))
+            .with_context(|| format!("unknown difficulty {name}"))?,
+        None => Difficulty::Medium
+    };
+    let mut ai = Ai::new(difficulty);
+    if let Some(path) = args.get(2) {
+        let book = Book::load(path).with_context(|| format!("failed to load book {path}"))?;
+        ai.set_book(Some(Arc::new(book)));
+    }
+
+    let mut stdout = io::stdout().lock();
+    let mut game = None;
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let Ok(command) = line.parse() else {
+            // The last position no longer stands, so "go" must not search it
+            if line.split_whitespace().next() == Some("position") {
+                game = None;
+            }
+            continue;
+        };
+        match command {
+            Command::Hello => {
+                writeln!(stdout, "id name c4 ({difficulty:?})")?;
+                writeln!(stdout, "c4engineok")?;
+            },
+            Command::Position { .. } => game = command.to_game(),
+            Command::Go(limits) => {
+                let deadline = deadline(&limits, Instant::now());
+                let (mv, info) = match game.as_ref().and_then(|game| search(&mut ai, game, deadline)) {
+                    Some((mv, info)) => (Some(mv), info),
+                    None => (None, None)
+                };
+                if let Some(info) = info {
+                    writeln!(stdout, "{}", Response::Info(info))?;
+                }
+                writeln!(stdout, "{}", Response::BestMove(mv))?;
+            },
+            // Searches finish before the next command is read
+            Command::Stop => {},
+            Command::Quit => break
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+// When to stop checking moves: after the move time, or a twentieth of the clock and half
+// the increment
+fn deadline(limits: &Limits, start: Instant) -> Option<Instant> {
+    let share = limits.time.map(|time| time.min(time / 20 + limits.inc.unwrap_or_default() / 2));
+    [limits.movetime, share].into_iter().flatten().min().map(|budget| start + budget)
+}
+
+fn search(ai: &mut Ai, game: &Game, deadline: Option<Instant>) -> Option<(Move, Option<Info>)> {
+    if game.is_over() {
+        return None;
+    }
+    let Ok(pos) = Position::try_from(game) else {
+        return Some((play_by_rules(game, deadline)?, None));
+    };
+    let (col, score) = ai.choose_scored_move(&pos)?;
+    let score = if ai.use_solver(&pos) {
+        Score::Exact(Evaluation::from_score(&pos, score))
+    } else {
+        Score::Heuristic(score)
+    };
+    let info = Info { depth: Some(ai.difficulty().depth()), score: Some(score), ..Info::default() };
+    Some((Move::Drop(col), Some(info)))
+}
+
+// Whether the player to move wins by playing the move
+fn wins(game: &Game, mv: Move) -> bool {
+    let tile = game.current_player();
+    let cell = match mv {
+        Move::Drop(col) => game.grid().drop_row(col).map(|row| (row, col)),
+        Move::Place(row, col) => Some((row, col)),
+        _ => None
+    };
+    match cell {
+        // Adding a single disc can only complete lines through it
+        Some((row, col)) if game.rules().scoring == Scoring::Normal => game.completes_line(row, col, tile),
+        _ => {
+            let mut next = game.clone();
+            next.play_move(mv).is_ok() && next.win() == Some(tile)
+        }
+    }
+}
+
+fn play_by_rules(game: &Game, deadline: Option<Instant>) -> Option<Move> {
+    let mut moves = game.legal_moves();
+    if let Some(&mv) = moves.iter().find(|&&mv| wins(game, mv)) {
+        return Some(mv);
+    }
+
+    // Checked in random order, so running out of time leaves a random safe move
+    let mut rng = rand::thread_rng();
+    moves.shuffle(&mut rng);
+    let mut safe = Vec::new();
+    for &mv in &moves {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+        let mut next = game.clone();
+        if next.play_move(mv).is_ok() && !next.legal_moves().into_iter().any(|reply| wins(&next, reply)) {
+            safe.push(mv);
+        }
+    }
+    let candidates = if safe.is_empty() { &moves } else { &safe };
+    candidates.choose(&mut rng).copied()
+}
@@ -0,0 +1,343 @@
+// Plays engines against each other in standard games and ranks them
+//
+// Usage: c4-tournament [options] ENGINE ENGINE...
+//
+// An engine is a difficulty of the built-in AI (easy, medium, hard, expert or perfect)
+// or the command line of an engine program, quoted if it has arguments. Options:
+//
+//   --gauntlet        the first engine plays each of the others, instead of everyone
+//                     playing everyone
+//   --rounds N        openings per pairing, each played once with either colour (10)
+//   --plies N         length of the random openings, under 42 (4)
+//   --movetime MS     thinking time per move for engine programs (1000)
+//   --concurrency N   games played at once (1)
+//   --records DIR     directory to save every game's record in
+//   --book PATH       opening book for the built-in AI
+//
+// A player that exits, sends an illegal move or takes far longer than its move time
+// loses the game; its record is saved unfinished with a Termination header.
+
+use std::{env, fs, path::PathBuf, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread, time::{Duration, Instant}};
+
+use anyhow::{bail, Context};
+use c4::{ai::{Ai, Difficulty}, bitboard::{Position, HEIGHT, WIDTH}, book::Book, engine::{Engine, Limits, GRACE}, game::{Game, Move, Rules, Tile}, player::{Computer, Player, PlayerError}, record::{result_string, GameRecord}};
+use rand::Rng;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+// Openings after which either side is a whole threat ahead at Medium depth are rerolled,
+// up to a limit for when balanced ones are too rare to find
+const BALANCE: i32 = 100;
+const MAX_REROLLS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Spec {
+    Builtin(Difficulty),
+    Program(Vec<String>)
+}
+
+impl Spec {
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        if let Some(&difficulty) = Difficulty::ALL.iter().find(|difficulty| format!("{difficulty:?}").eq_ignore_ascii_case(s)) {
+            return Ok(Spec::Builtin(difficulty));
+        }
+        let command: Vec<_> = s.split_whitespace().map(str::to_owned).collect();
+        if command.is_empty() {
+            bail!("empty engine command");
+        }
+        Ok(Spec::Program(command))
+    }
+
+    fn player(&self, opts: &Options) -> Result<Box<dyn Player>, PlayerError> {
+        match self {
+            Spec::Builtin(difficulty) => Ok(Box::new(Computer::new(*difficulty, opts.book.clone()))),
+            Spec::Program(command) => {
+                let limits = Limits { movetime: Some(opts.movetime), ..Limits::default() };
+                Ok(Box::new(Engine::spawn(&command[0], &command[1..], limits)?))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Options {
+    engines: Vec<(String, Spec)>,
+    gauntlet: bool,
+    rounds: usize,
+    plies: usize,
+    movetime: Duration,
+    concurrency: usize,
+    records: Option<PathBuf>,
+    book: Option<Arc<Book>>
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut opts = Self {
+            engines: Vec::new(),
+            gauntlet: false,
+            rounds: 10,
+            plies: 4,
+            movetime: Duration::from_secs(1),
+            concurrency: 1,
+            records: None,
+            book: None
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--gauntlet" => opts.gauntlet = true,
+                "--rounds" => {
+                    opts.rounds = args.next().context("--rounds needs a number")?.parse().context("invalid number of rounds")?;
+                },
+                "--plies" => {
+                    opts.plies = args.next().context("--plies needs a number")?.parse().context("invalid number of plies")?;
+                },
+                "--movetime" => {
+                    let ms = args.next().context("--movetime needs milliseconds")?.parse().context("invalid move time")?;
+                    opts.movetime = Duration::from_millis(ms);
+                },
+                "--concurrency" => {
+                    opts.concurrency = args.next().context("--concurrency needs a number")?.parse().context("invalid concurrency")?;
+                },
+                "--records" => {
+                    opts.records = Some(args.next().context("--records needs a directory")?.into());
+                },
+                "--book" => {
+                    let path = args.next().context("--book needs a path")?;
+                    let book = Book::load(&path).with_context(|| format!("failed to load book {path}"))?;
+                    opts.book = Some(Arc::new(book));
+                },
+                _ if arg.starts_with("--") => bail!("unknown argument {arg}"),
+                _ => opts.engines.push((arg.clone(), Spec::parse(&arg)?))
+            }
+        }
+        // A full board has already ended the game
+        if opts.plies >= WIDTH * HEIGHT {
+            bail!("openings must be shorter than {} plies", WIDTH * HEIGHT);
+        }
+        if opts.engines.len() < 2 {
+            bail!("at least two engines are needed");
+        }
+        Ok(opts)
+    }
+}
+
+// One game: the engines playing Red and Yellow and the opening to start from
+#[derive(Debug)]
+struct Job {
+    players: [usize; 2],
+    opening: usize
+}
+
+#[derive(Debug)]
+struct Outcome {
+    // Red's score: 1 for a win, 1/2 for a draw
+    score: f64,
+    game: Game,
+    forfeit: Option<String>
+}
+
+fn main() -> anyhow::Result<()> {
+    let opts = Options::parse(env::args().skip(1))?;
+    // Make sure every engine program starts before playing any games
+    for (name, spec) in &opts.engines {
+        spec.player(&opts).with_context(|| format!("failed to start {name}"))?;
+    }
+    if let Some(dir) = &opts.records {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    let openings = (0..opts.rounds).map(|_| random_opening(opts.plies)).collect::<anyhow::Result<Vec<_>>>()?;
+    let pairings: Vec<_> = if opts.gauntlet {
+        (1..opts.engines.len()).map(|other| (0, other)).collect()
+    } else {
+        (0..opts.engines.len()).flat_map(|a| (a + 1..opts.engines.len()).map(move |b| (a, b))).collect()
+    };
+    let jobs: Vec<_> = pairings.iter()
+        .flat_map(|&(a, b)| (0..opts.rounds).flat_map(move |opening| [
+            Job { players: [a, b], opening },
+            Job { players: [b, a], opening }
+        ]))
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results: Vec<(usize, f64)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..opts.concurrency.max(1))
+            .map(|_| s.spawn(|| {
+                let mut results = Vec::new();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index) else {
+                        break results;
+                    };
+                    let outcome = play(&opts, job, &openings[job.opening]);
+                    let [red, yellow] = job.players.map(|id| opts.engines[id].0.as_str());
+                    let number = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    match &outcome.forfeit {
+                        Some(reason) => println!("Game {number}/{}: {red} vs {yellow}: {reason}", jobs.len()),
+                        None => println!("Game {number}/{}: {red} vs {yellow}: {}", jobs.len(), result_string(outcome.game.outcome(), 2))
+                    }
+                    if let Some(dir) = &opts.records {
+                        let mut record = GameRecord::from_game(&outcome.game, &[red, yellow]);
+                        record.set_header("Round", &(index + 1).to_string());
+                        if let Some(reason) = &outcome.forfeit {
+                            record.set_header("Termination", reason);
+                        }
+                        let path = dir.join(format!("game-{:04}.c4", index + 1));
+                        if let Err(e) = record.save(&path) {
+                            println!("Failed to save {}: {e}", path.display());
+                        }
+                    }
+                    results.push((index, outcome.score));
+                }
+            }))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+
+    // Each engine's score in every game it played
+    let mut scores = vec![Vec::new(); opts.engines.len()];
+    for (index, score) in results {
+        let [red, yellow] = jobs[index].players;
+        scores[red].push(score);
+        scores[yellow].push(1. - score);
+    }
+    let mut table: Vec<_> = opts.engines.iter().zip(&scores)
+        .map(|((name, _), scores)| (name, scores.len(), scores.iter().sum::<f64>(), elo(scores)))
+        .collect();
+    table.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    println!();
+    println!("{:<4} {:<24} {:>5} {:>6} {:>6} {:>5}", "Rank", "Engine", "Games", "Score", "Elo", "+/-");
+    for (rank, (name, games, score, elo)) in table.into_iter().enumerate() {
+        let (elo, margin) = match elo {
+            Some((elo, margin)) => (format!("{elo:+.0}"), format!("{margin:.0}")),
+            None => ("-".to_owned(), "-".to_owned())
+        };
+        println!("{:<4} {:<24} {games:>5} {score:>6.1} {elo:>6} {margin:>5}", rank + 1, name);
+    }
+    Ok(())
+}
+
+// Random moves that neither end the game nor leave either side clearly ahead
+fn random_opening(plies: usize) -> anyhow::Result<Vec<Move>> {
+    let mut rng = rand::thread_rng();
+    let mut ai = Ai::new(Difficulty::Medium);
+    for _ in 0..MAX_REROLLS {
+        let mut game = Game::new(Rules::STANDARD);
+        while game.history().len() < plies && !game.is_over() {
+            let _ = game.play(rng.gen_range(0..Rules::STANDARD.cols));
+        }
+        if game.is_over() {
+            continue;
+        }
+        let pos = Position::try_from(&game).expect("standard game");
+        let best = ai.score_moves(&pos).into_iter().map(|(_, score)| score).max();
+        if best.is_some_and(|best| best.abs() < BALANCE) {
+            return Ok(game.history().to_vec());
+        }
+    }
+    bail!("no balanced opening of {plies} plies found in {MAX_REROLLS} tries")
+}
+
+fn play(opts: &Options, job: &Job, opening: &[Move]) -> Outcome {
+    let mut game = Game::new(Rules::STANDARD);
+    for &mv in opening {
+        game.play_move(mv).expect("openings are legal");
+    }
+
+    // The losing side's score when a player forfeits
+    let forfeit = |game: Game, tile: Tile, reason: String| Outcome {
+        score: if tile == Tile::RED { 0. } else { 1. },
+        game,
+        forfeit: Some(format!("{tile} forfeits: {reason}"))
+    };
+    let mut players = Vec::new();
+    for (&id, tile) in job.players.iter().zip([Tile::RED, Tile::YELLOW]) {
+        match opts.engines[id].1.player(opts) {
+            Ok(player) => players.push(player),
+            Err(e) => return forfeit(game, tile, e.to_string())
+        }
+    }
+
+    while !game.is_over() {
+        let tile = game.current_player();
+        let deadline = Instant::now() + opts.movetime + GRACE;
+        let mv = loop {
+            match players[tile.id()].poll_move(&game) {
+                Ok(Some(mv)) => break mv,
+                Ok(None) if Instant::now() > deadline => return forfeit(game, tile, "out of time".to_owned()),
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => return forfeit(game, tile, e.to_string())
+            }
+        };
+        if let Err(e) = game.play_move(mv) {
+            return forfeit(game, tile, format!("illegal move {mv} ({e})"));
+        }
+        for player in &mut players {
+            let _ = player.move_played(&game, mv);
+        }
+    }
+
+    let score = match game.win() {
+        Some(Tile::RED) => 1.,
+        Some(_) => 0.,
+        None => 0.5
+    };
+    Outcome { score, game, forfeit: None }
+}
+
+// Performance rating relative to the opponents faced, with the margin of a 95%
+// confidence interval, from the mean and spread of the game scores
+fn elo(scores: &[f64]) -> Option<(f64, f64)> {
+    let n = scores.len() as f64;
+    if scores.is_empty() {
+        return None;
+    }
+    let mean = scores.iter().sum::<f64>() / n;
+    let variance = scores.iter().map(|score| (score - mean).powi(2)).sum::<f64>() / n;
+    let margin = 1.96 * (variance / n).sqrt();
+
+    let rating = |p: f64| -400. * (1. / p.clamp(1e-3, 1. - 1e-3) - 1.).log10();
+    Some((rating(mean), (rating(mean + margin) - rating(mean - margin)) / 2.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> anyhow::Result<Options> {
+        Options::parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn plies_must_leave_the_game_open() {
+        assert_eq!(options(&["easy", "medium", "--plies", "41"]).unwrap().plies, 41);
+        assert!(options(&["easy", "medium", "--plies", "42"]).is_err());
+        assert!(options(&["easy", "medium", "--plies", "1000"]).is_err());
+    }
+
+    #[test]
+    fn openings_are_balanced() {
+        let opening = random_opening(4).unwrap();
+        assert_eq!(opening.len(), 4);
+        assert_eq!(random_opening(0).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn elo_of_all_wins_and_draws() {
+        assert_eq!(elo(&[]), None);
+        assert_eq!(elo(&[0.5; 10]), Some((0., 0.)));
+
+        // Perfect scores are capped rather than infinite
+        let (rating, margin) = elo(&[1.; 10]).unwrap();
+        assert!(rating > 1000. && rating.is_finite());
+        assert_eq!(margin, 0.);
+        let (rating, _) = elo(&[0.; 10]).unwrap();
+        assert!(rating < -1000. && rating.is_finite());
+
+        let (rating, margin) = elo(&[1., 0., 1., 0.]).unwrap();
+        assert!(rating.abs() < 1e-9);
+        assert!(margin > 0.);
+    }
+}
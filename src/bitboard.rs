@@ -0,0 +1,407 @@
+// Bitboard position for fast search on the standard 7x6 board
+//
+// Each column takes HEIGHT+1 bits, bottom row first, with a spare bit on top
+// so that shifts never carry from one column into the next:
+//
+//  6 13 20 27 34 41 48
+//  5 12 19 26 33 40 47
+//  4 11 18 25 32 39 46
+//  3 10 17 24 31 38 45
+//  2  9 16 23 30 37 44
+//  1  8 15 22 29 36 43
+//  0  7 14 21 28 35 42
+
+use std::{error::Error, fmt};
+
+use crate::game::{Game, Grid, Rules, Tile};
+
+pub const WIDTH: usize = Rules::STANDARD.cols;
+pub const HEIGHT: usize = Rules::STANDARD.rows;
+
+const H1: usize = HEIGHT + 1;
+
+const fn bottom_mask() -> u64 {
+    let mut mask = 0;
+    let mut col = 0;
+    while col < WIDTH {
+        mask |= 1 << (col * H1);
+        col += 1;
+    }
+    mask
+}
+
+const BOTTOM: u64 = bottom_mask();
+const BOARD: u64 = BOTTOM * ((1 << HEIGHT) - 1);
+
+const fn bottom_mask_col(col: usize) -> u64 {
+    1 << (col * H1)
+}
+
+const fn top_mask_col(col: usize) -> u64 {
+    1 << (HEIGHT - 1 + col * H1)
+}
+
+pub const fn column_mask(col: usize) -> u64 {
+    ((1 << HEIGHT) - 1) << (col * H1)
+}
+
+fn cell_bit(row: usize, col: usize) -> u64 {
+    // `row` counts from the top, as in `Grid`
+    1 << (HEIGHT - 1 - row + col * H1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionError {
+    // A tile at (row, col) with an empty cell below it
+    Floating(usize, usize),
+    // Red moves first, so it has either as many tiles as Yellow or one more
+    TileCount { red: usize, yellow: usize },
+    // Only standard games fit in a bitboard
+    BoardSize(usize, usize),
+    Rules(Rules),
+    // Bitboards hold two players
+    Player(Tile)
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::Floating(row, col) => write!(f, "tile at row {}, column {} is floating", row + 1, col + 1),
+            PositionError::TileCount { red, yellow } => write!(f, "{red} red and {yellow} yellow tiles cannot occur in a game"),
+            PositionError::BoardSize(cols, rows) => write!(f, "a {cols}x{rows} board does not fit in a bitboard"),
+            PositionError::Rules(rules) => write!(
+                f, "{} {}x{} connect {} for {} players with {} scoring is not the standard game",
+                rules.variant, rules.cols, rules.rows, rules.connect, rules.players, rules.scoring
+            ),
+            PositionError::Player(tile) => write!(f, "{tile} cannot play in a two-player game")
+        }
+    }
+}
+
+impl Error for PositionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    // Tiles of the player to move
+    current: u64,
+    // All tiles
+    mask: u64,
+    heights: [u8; WIDTH],
+    moves: u32
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Position {
+    pub fn new() -> Self {
+        Self {
+            current: 0,
+            mask: 0,
+            heights: [0; WIDTH],
+            moves: 0
+        }
+    }
+
+    pub fn from_grid(grid: &Grid) -> Result<Self, PositionError> {
+        if grid.cols() != WIDTH || grid.rows() != HEIGHT {
+            return Err(PositionError::BoardSize(grid.cols(), grid.rows()));
+        }
+
+        let mut red = 0;
+        let mut yellow = 0;
+        let mut red_count = 0;
+        let mut yellow_count = 0;
+        let mut heights = [0; WIDTH];
+
+        for (col, height) in heights.iter_mut().enumerate() {
+            for row in (0..HEIGHT).rev() {
+                if let Some(tile) = grid.get(row, col) {
+                    if (*height as usize) < HEIGHT - 1 - row {
+                        return Err(PositionError::Floating(row, col));
+                    }
+                    *height += 1;
+                    match tile {
+                        Tile::RED => {
+                            red |= cell_bit(row, col);
+                            red_count += 1;
+                        },
+                        Tile::YELLOW => {
+                            yellow |= cell_bit(row, col);
+                            yellow_count += 1;
+                        },
+                        tile => return Err(PositionError::Player(tile))
+                    }
+                }
+            }
+        }
+
+        if red_count != yellow_count && red_count != yellow_count + 1 {
+            return Err(PositionError::TileCount { red: red_count, yellow: yellow_count });
+        }
+
+        let moves = (red_count + yellow_count) as u32;
+        let current = if moves.is_multiple_of(2) { red } else { yellow };
+
+        Ok(Self { current, mask: red | yellow, heights, moves })
+    }
+
+    pub fn to_grid(&self) -> Grid {
+        let mut grid = Grid::new(WIDTH, HEIGHT);
+        let player = self.current_player();
+        for row in 0..HEIGHT {
+            for col in 0..WIDTH {
+                let bit = cell_bit(row, col);
+                if self.mask & bit != 0 {
+                    grid.set(row, col, Some(if self.current & bit != 0 { player } else { player.other() }));
+                }
+            }
+        }
+        grid
+    }
+
+    pub fn current_player(&self) -> Tile {
+        if self.moves.is_multiple_of(2) { Tile::RED } else { Tile::YELLOW }
+    }
+
+    pub fn moves(&self) -> u32 {
+        self.moves
+    }
+
+    pub fn height(&self, col: usize) -> usize {
+        self.heights[col] as usize
+    }
+
+    pub fn current_mask(&self) -> u64 {
+        self.current
+    }
+
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    // Unique key for transposition tables
+    pub fn key(&self) -> u64 {
+        self.current + self.mask
+    }
+
+    // Shared by a position and its mirror image
+    pub fn canonical_key(&self) -> u64 {
+        self.key().min(self.mirror().key())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.moves as usize == HEIGHT * WIDTH
+    }
+
+    pub fn can_play(&self, col: usize) -> bool {
+        self.mask & top_mask_col(col) == 0
+    }
+
+    // The column must not be full; check `can_play` first
+    pub fn play(&mut self, col: usize) {
+        debug_assert!(self.can_play(col), "column {col} is full");
+        self.play_mask((self.mask + bottom_mask_col(col)) & column_mask(col));
+    }
+
+    // Plays the single-bit move `mv`, which must be in `possible()`
+    pub fn play_mask(&mut self, mv: u64) {
+        self.current ^= self.mask;
+        self.mask |= mv;
+        self.heights[mv.trailing_zeros() as usize / H1] += 1;
+        self.moves += 1;
+    }
+
+    // Whether the player who just moved has four in a row
+    pub fn last_move_won(&self) -> bool {
+        alignment(self.current ^ self.mask)
+    }
+
+    pub fn is_winning_move(&self, col: usize) -> bool {
+        self.winning_position() & self.possible() & column_mask(col) != 0
+    }
+
+    pub fn can_win_next(&self) -> bool {
+        self.winning_position() & self.possible() != 0
+    }
+
+    // Cells where a tile may be dropped right now
+    pub fn possible(&self) -> u64 {
+        (self.mask + BOTTOM) & BOARD
+    }
+
+    // Playable moves that do not hand the opponent an immediate win
+    pub fn non_losing_moves(&self) -> u64 {
+        let mut possible = self.possible();
+        let opponent_win = self.opponent_winning_position();
+        let forced = possible & opponent_win;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                // The opponent has two threats, we cannot block both
+                return 0;
+            }
+            possible = forced;
+        }
+        possible & !(opponent_win >> 1)
+    }
+
+    // Empty cells that would complete four for the player to move
+    pub fn winning_position(&self) -> u64 {
+        winning_cells(self.current, self.mask)
+    }
+
+    pub fn opponent_winning_position(&self) -> u64 {
+        winning_cells(self.current ^ self.mask, self.mask)
+    }
+
+    // Number of cells the move would turn into winning cells
+    pub fn move_score(&self, mv: u64) -> u32 {
+        (winning_cells(self.current | mv, self.mask) & !(self.mask | mv)).count_ones()
+    }
+
+    pub fn mirror(&self) -> Self {
+        let mut current = 0;
+        let mut mask = 0;
+        let mut heights = self.heights;
+        for col in 0..WIDTH {
+            let from = col * H1;
+            let to = (WIDTH - 1 - col) * H1;
+            current |= ((self.current >> from) & column_mask(0)) << to;
+            mask |= ((self.mask >> from) & column_mask(0)) << to;
+            heights[WIDTH - 1 - col] = self.heights[col];
+        }
+        Self { current, mask, heights, moves: self.moves }
+    }
+}
+
+impl TryFrom<&Game> for Position {
+    type Error = PositionError;
+
+    fn try_from(game: &Game) -> Result<Self, Self::Error> {
+        if game.rules() != Rules::STANDARD {
+            return Err(PositionError::Rules(game.rules()));
+        }
+        Self::from_grid(game.grid())
+    }
+}
+
+fn alignment(pos: u64) -> bool {
+    // Horizontal, diagonal /, diagonal \, vertical
+    [H1, H1 + 1, H1 - 1, 1].into_iter().any(|shift| {
+        let m = pos & (pos >> shift);
+        m & (m >> (2 * shift)) != 0
+    })
+}
+
+fn winning_cells(pos: u64, mask: u64) -> u64 {
+    // Vertical
+    let mut r = (pos << 1) & (pos << 2) & (pos << 3);
+
+    for shift in [H1, H1 + 1, H1 - 1] {
+        let mut p = (pos << shift) & (pos << (2 * shift));
+        r |= p & (pos << (3 * shift));
+        r |= p & (pos >> shift);
+        p = (pos >> shift) & (pos >> (2 * shift));
+        r |= p & (pos << shift);
+        r |= p & (pos >> (3 * shift));
+    }
+
+    r & (BOARD ^ mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    // Random games, as the game and the position after every move
+    fn random_games(count: usize) -> Vec<Vec<(Game, Position)>> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..count).map(|_| {
+            let mut game = Game::default();
+            let mut pos = Position::new();
+            let mut states = vec![(game.clone(), pos)];
+            while !game.is_over() {
+                let moves = game.legal_moves();
+                let col = moves[rng.gen_range(0..moves.len())].col().unwrap();
+                game.play(col).unwrap();
+                pos.play(col);
+                states.push((game.clone(), pos));
+            }
+            states
+        }).collect()
+    }
+
+    #[test]
+    fn grid_round_trip() {
+        for (game, pos) in random_games(50).into_iter().flatten() {
+            assert_eq!(Position::from_grid(game.grid()), Ok(pos));
+            assert_eq!(pos.to_grid(), *game.grid());
+            assert_eq!(Position::try_from(&game), Ok(pos));
+        }
+    }
+
+    #[test]
+    fn from_grid_errors() {
+        let mut grid = Grid::new(WIDTH, HEIGHT);
+        grid.set(4, 2, Some(Tile::RED));
+        assert_eq!(Position::from_grid(&grid), Err(PositionError::Floating(4, 2)));
+        assert_eq!(PositionError::Floating(4, 2).to_string(), "tile at row 5, column 3 is floating");
+
+        let mut grid = Grid::new(WIDTH, HEIGHT);
+        grid.set(5, 0, Some(Tile::YELLOW));
+        assert_eq!(Position::from_grid(&grid), Err(PositionError::TileCount { red: 0, yellow: 1 }));
+        grid.set(5, 1, Some(Tile::RED));
+        grid.set(5, 2, Some(Tile::RED));
+        grid.set(5, 3, Some(Tile::RED));
+        assert_eq!(Position::from_grid(&grid), Err(PositionError::TileCount { red: 3, yellow: 1 }));
+
+        assert_eq!(Position::from_grid(&Grid::new(8, 6)), Err(PositionError::BoardSize(8, 6)));
+        let rules = Rules { cols: 8, ..Rules::STANDARD };
+        assert_eq!(Position::try_from(&Game::new(rules)), Err(PositionError::Rules(rules)));
+    }
+
+    #[test]
+    fn wins_match_the_game() {
+        for states in random_games(200) {
+            for pair in states.windows(2) {
+                let [(game, pos), (next, next_pos)] = pair else {
+                    unreachable!()
+                };
+                let col = next.last_move().unwrap().col().unwrap();
+                let won = next.win().is_some();
+                assert_eq!(next_pos.last_move_won(), won);
+                assert_eq!(pos.is_winning_move(col), won);
+                assert_eq!(pos.can_win_next(), game.legal_moves().iter().any(|&mv| {
+                    let mut game = game.clone();
+                    game.play_move(mv).is_ok() && game.win().is_some()
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn mirror_reverses_columns() {
+        for (game, pos) in random_games(20).into_iter().flatten() {
+            let grid = pos.mirror().to_grid();
+            for (row, col) in (0..HEIGHT).flat_map(|row| (0..WIDTH).map(move |col| (row, col))) {
+                assert_eq!(grid.get(row, col), game.grid().get(row, WIDTH - 1 - col));
+            }
+            assert_eq!(pos.canonical_key(), pos.mirror().canonical_key());
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "column 0 is full")]
+    fn play_in_a_full_column() {
+        let mut pos = Position::new();
+        for _ in 0..=HEIGHT {
+            pos.play(0);
+        }
+    }
+}
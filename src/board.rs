@@ -1,16 +1,12 @@
-use std::{f32::consts::PI, mem, num::NonZero};
+use std::{f32::consts::{FRAC_PI_2, PI}, mem, num::NonZero, ops::Range, time::{Duration, Instant}};
 
 use bytemuck::{Pod, Zeroable, cast_slice, cast_slice_mut};
-use nalgebra::{Isometry, Matrix4, Point3, Translation3, UnitQuaternion, Vector3};
+use nalgebra::{Isometry, Isometry3, Matrix4, Point3, Translation3, UnitQuaternion, Vector3};
 use wgpu::{*, util::{BufferInitDescriptor, DeviceExt as _}};
 
-use crate::camera::Camera;
-
-const ROWS: usize = 6;
-const COLS: usize = 7;
+use c4::{engine::Score, game::{Disc, Game, Grid, MAX_PLAYERS, Move, Power, Rotation, Tile}, solver::Evaluation};
 
-const HALF_ROWS: f32 = ROWS as f32 / 2.;
-const HALF_COLS: f32 = COLS as f32 / 2.;
+use crate::camera::Camera;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -20,31 +16,35 @@ struct BoardVertex {
     normal: [f32; 3]
 }
 
-const BOARD_VERTICES: &[BoardVertex] = &[
-    // Front
-    BoardVertex { position: [-HALF_COLS, HALF_ROWS, 0.1], coord: [0., 0.], normal: [0., 0., 1.] },  // top left
-    BoardVertex { position: [-HALF_COLS, -HALF_ROWS, 0.1], coord: [0., ROWS as f32], normal: [0., 0., 1.] }, // bottom left
-    BoardVertex { position: [HALF_COLS, -HALF_ROWS, 0.1], coord: [COLS as f32, ROWS as f32], normal: [0., 0., 1.] },  // bottom right
-    BoardVertex { position: [HALF_COLS, HALF_ROWS, 0.1], coord: [COLS as f32, 0.], normal: [0., 0., 1.] },   // top right
+fn board_vertices(cols: usize, rows: usize) -> [BoardVertex; 16] {
+    let (cols, rows) = (cols as f32, rows as f32);
+    let (half_cols, half_rows) = (cols / 2., rows / 2.);
+    [
+        // Front
+        BoardVertex { position: [-half_cols, half_rows, 0.1], coord: [0., 0.], normal: [0., 0., 1.] },  // top left
+        BoardVertex { position: [-half_cols, -half_rows, 0.1], coord: [0., rows], normal: [0., 0., 1.] }, // bottom left
+        BoardVertex { position: [half_cols, -half_rows, 0.1], coord: [cols, rows], normal: [0., 0., 1.] },  // bottom right
+        BoardVertex { position: [half_cols, half_rows, 0.1], coord: [cols, 0.], normal: [0., 0., 1.] },   // top right
 
-    // Back
-    BoardVertex { position: [-HALF_COLS, HALF_ROWS, -0.1], coord: [COLS as f32, 0.], normal: [0., 0., -1.] },  // top right
-    BoardVertex { position: [HALF_COLS, -HALF_ROWS, -0.1], coord: [0., ROWS as f32], normal: [0., 0., -1.] },  // bottom left
-    BoardVertex { position: [-HALF_COLS, -HALF_ROWS, -0.1], coord: [COLS as f32, ROWS as f32], normal: [0., 0., -1.] }, // bottom right
-    BoardVertex { position: [HALF_COLS, HALF_ROWS, -0.1], coord: [0., 0.], normal: [0., 0., -1.] },   // top left
+        // Back
+        BoardVertex { position: [-half_cols, half_rows, -0.1], coord: [cols, 0.], normal: [0., 0., -1.] },  // top right
+        BoardVertex { position: [half_cols, -half_rows, -0.1], coord: [0., rows], normal: [0., 0., -1.] },  // bottom left
+        BoardVertex { position: [-half_cols, -half_rows, -0.1], coord: [cols, rows], normal: [0., 0., -1.] }, // bottom right
+        BoardVertex { position: [half_cols, half_rows, -0.1], coord: [0., 0.], normal: [0., 0., -1.] },   // top left
 
-    // Left
-    BoardVertex { position: [-HALF_COLS, HALF_ROWS, -0.1], coord: [0., 0.], normal: [-1., 0., 0.] },   // top left
-    BoardVertex { position: [-HALF_COLS, -HALF_ROWS, -0.1], coord: [0., 0.], normal: [-1., 0., 0.] },  // bottom left
-    BoardVertex { position: [-HALF_COLS, HALF_ROWS, 0.1], coord: [0., 0.], normal: [-1., 0., 0.] },    // top right
-    BoardVertex { position: [-HALF_COLS, -HALF_ROWS, 0.1], coord: [0., 0.], normal: [-1., 0., 0.] },   // bottom right
+        // Left
+        BoardVertex { position: [-half_cols, half_rows, -0.1], coord: [0., 0.], normal: [-1., 0., 0.] },   // top left
+        BoardVertex { position: [-half_cols, -half_rows, -0.1], coord: [0., 0.], normal: [-1., 0., 0.] },  // bottom left
+        BoardVertex { position: [-half_cols, half_rows, 0.1], coord: [0., 0.], normal: [-1., 0., 0.] },    // top right
+        BoardVertex { position: [-half_cols, -half_rows, 0.1], coord: [0., 0.], normal: [-1., 0., 0.] },   // bottom right
 
-    // Right
-    BoardVertex { position: [HALF_COLS, HALF_ROWS, -0.1], coord: [0., 0.], normal: [1., 0., 0.] },   // top right
-    BoardVertex { position: [HALF_COLS, HALF_ROWS, 0.1], coord: [0., 0.], normal: [1., 0., 0.] },    // top left
-    BoardVertex { position: [HALF_COLS, -HALF_ROWS, -0.1], coord: [0., 0.], normal: [1., 0., 0.] },  // bottom right
-    BoardVertex { position: [HALF_COLS, -HALF_ROWS, 0.1], coord: [0., 0.], normal: [1., 0., 0.] },   // bottom left
-];
+        // Right
+        BoardVertex { position: [half_cols, half_rows, -0.1], coord: [0., 0.], normal: [1., 0., 0.] },   // top right
+        BoardVertex { position: [half_cols, half_rows, 0.1], coord: [0., 0.], normal: [1., 0., 0.] },    // top left
+        BoardVertex { position: [half_cols, -half_rows, -0.1], coord: [0., 0.], normal: [1., 0., 0.] },  // bottom right
+        BoardVertex { position: [half_cols, -half_rows, 0.1], coord: [0., 0.], normal: [1., 0., 0.] },   // bottom left
+    ]
+}
 
 const BOARD_INDICES: &[u16] = &[
     // Front
@@ -57,6 +57,74 @@ const BOARD_INDICES: &[u16] = &[
     12, 13, 14, 14, 13, 15
 ];
 
+// Subdivisions of each column's arc when the board is drawn as a cylinder
+const CYLINDER_SEGMENTS: usize = 4;
+// Half the thickness of the cylinder wall, enough to cover the flat tiles where it curves
+const CYLINDER_HALF_THICKNESS: f32 = 0.25;
+
+// Gives each column one unit of circumference
+fn cylinder_radius(cols: usize) -> f32 {
+    cols as f32 / (2. * PI)
+}
+
+// Base plate with a hole under each Score Four peg
+fn plate_mesh(side: usize, rows: usize) -> (Vec<BoardVertex>, Vec<u16>) {
+    let half = side as f32 / 2.;
+    let (top, bottom) = (-(rows as f32) / 2., -(rows as f32) / 2. - 0.2);
+    let size = side as f32;
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    // Corners in counter-clockwise order seen from outside, as top left, bottom left, bottom right, top right
+    let mut quad = |corners: [[f32; 3]; 4], coords: [[f32; 2]; 4], normal: [f32; 3]| {
+        let base = vertices.len() as u16;
+        for (position, coord) in corners.into_iter().zip(coords) {
+            vertices.push(BoardVertex { position, coord, normal });
+        }
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    };
+    let holes = [[0., 0.], [0., size], [size, size], [size, 0.]];
+    let solid = [[0., 0.]; 4];
+
+    quad([[-half, top, -half], [-half, top, half], [half, top, half], [half, top, -half]], holes, [0., 1., 0.]);
+    quad([[-half, bottom, half], [-half, bottom, -half], [half, bottom, -half], [half, bottom, half]], [holes[1], holes[0], holes[3], holes[2]], [0., -1., 0.]);
+    quad([[-half, top, half], [-half, bottom, half], [half, bottom, half], [half, top, half]], solid, [0., 0., 1.]);
+    quad([[half, top, -half], [half, bottom, -half], [-half, bottom, -half], [-half, top, -half]], solid, [0., 0., -1.]);
+    quad([[half, top, half], [half, bottom, half], [half, bottom, -half], [half, top, -half]], solid, [1., 0., 0.]);
+    quad([[-half, top, -half], [-half, bottom, -half], [-half, bottom, half], [-half, top, half]], solid, [-1., 0., 0.]);
+    (vertices, indices)
+}
+
+fn cylinder_mesh(cols: usize, rows: usize) -> (Vec<BoardVertex>, Vec<u16>) {
+    let radius = cylinder_radius(cols);
+    let half_rows = rows as f32 / 2.;
+    let steps = cols * CYLINDER_SEGMENTS;
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    // Outer wall facing out, then inner wall facing the axis
+    for (r, sign) in [(radius + CYLINDER_HALF_THICKNESS, 1.), ((radius - CYLINDER_HALF_THICKNESS).max(0.05), -1.)] {
+        let base = vertices.len() as u16;
+        for k in 0..=steps {
+            let angle = 2. * PI * k as f32 / steps as f32;
+            let (sin, cos) = angle.sin_cos();
+            let u = k as f32 / CYLINDER_SEGMENTS as f32;
+            let normal = [sign * sin, 0., sign * cos];
+            vertices.push(BoardVertex { position: [r * sin, half_rows, r * cos], coord: [u, 0.], normal });
+            vertices.push(BoardVertex { position: [r * sin, -half_rows, r * cos], coord: [u, rows as f32], normal });
+        }
+        for k in 0..steps as u16 {
+            let (top_left, bottom_left, top_right, bottom_right) = (base + 2 * k, base + 2 * k + 1, base + 2 * k + 2, base + 2 * k + 3);
+            if sign > 0. {
+                indices.extend([top_left, bottom_left, bottom_right, top_left, bottom_right, top_right]);
+            } else {
+                indices.extend([top_left, bottom_right, bottom_left, top_left, top_right, bottom_right]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 struct TileVertex {
@@ -135,31 +203,276 @@ const TILE_INDICES: &[u16] = &[
     12, 23, 18
 ];
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Tile {
-    Red,
-    Yellow
-}
+// Square block used for walls
+const BLOCK_VERTICES: &[TileVertex] = &[
+    // Front
+    TileVertex { position: [-0.4, 0.4, 0.1], normal: [0., 0., 1.] },
+    TileVertex { position: [-0.4, -0.4, 0.1], normal: [0., 0., 1.] },
+    TileVertex { position: [0.4, -0.4, 0.1], normal: [0., 0., 1.] },
+    TileVertex { position: [0.4, 0.4, 0.1], normal: [0., 0., 1.] },
+
+    // Back
+    TileVertex { position: [0.4, 0.4, -0.1], normal: [0., 0., -1.] },
+    TileVertex { position: [0.4, -0.4, -0.1], normal: [0., 0., -1.] },
+    TileVertex { position: [-0.4, -0.4, -0.1], normal: [0., 0., -1.] },
+    TileVertex { position: [-0.4, 0.4, -0.1], normal: [0., 0., -1.] },
+
+    // Right
+    TileVertex { position: [0.4, 0.4, 0.1], normal: [1., 0., 0.] },
+    TileVertex { position: [0.4, -0.4, 0.1], normal: [1., 0., 0.] },
+    TileVertex { position: [0.4, -0.4, -0.1], normal: [1., 0., 0.] },
+    TileVertex { position: [0.4, 0.4, -0.1], normal: [1., 0., 0.] },
+
+    // Left
+    TileVertex { position: [-0.4, 0.4, -0.1], normal: [-1., 0., 0.] },
+    TileVertex { position: [-0.4, -0.4, -0.1], normal: [-1., 0., 0.] },
+    TileVertex { position: [-0.4, -0.4, 0.1], normal: [-1., 0., 0.] },
+    TileVertex { position: [-0.4, 0.4, 0.1], normal: [-1., 0., 0.] },
+
+    // Top
+    TileVertex { position: [-0.4, 0.4, -0.1], normal: [0., 1., 0.] },
+    TileVertex { position: [-0.4, 0.4, 0.1], normal: [0., 1., 0.] },
+    TileVertex { position: [0.4, 0.4, 0.1], normal: [0., 1., 0.] },
+    TileVertex { position: [0.4, 0.4, -0.1], normal: [0., 1., 0.] },
+
+    // Bottom
+    TileVertex { position: [-0.4, -0.4, 0.1], normal: [0., -1., 0.] },
+    TileVertex { position: [-0.4, -0.4, -0.1], normal: [0., -1., 0.] },
+    TileVertex { position: [0.4, -0.4, -0.1], normal: [0., -1., 0.] },
+    TileVertex { position: [0.4, -0.4, 0.1], normal: [0., -1., 0.] },
+];
+
+const BLOCK_INDICES: &[u16] = &[
+    // Front
+    0, 1, 2, 0, 2, 3,
+    // Back
+    4, 5, 6, 4, 6, 7,
+    // Right
+    8, 9, 10, 8, 10, 11,
+    // Left
+    12, 13, 14, 12, 14, 15,
+    // Top
+    16, 17, 18, 16, 18, 19,
+    // Bottom
+    20, 21, 22, 20, 22, 23
+];
+
+const PEG_COLOR: [f32; 4] = [0.8, 0.7, 0.5, 1.];
+
+// One row's length of a Score Four peg
+const PEG_VERTICES: &[TileVertex] = &[
+    // Front
+    TileVertex { position: [-0.06, 0.5, 0.06], normal: [0., 0., 1.] },
+    TileVertex { position: [-0.06, -0.5, 0.06], normal: [0., 0., 1.] },
+    TileVertex { position: [0.06, -0.5, 0.06], normal: [0., 0., 1.] },
+    TileVertex { position: [0.06, 0.5, 0.06], normal: [0., 0., 1.] },
+
+    // Back
+    TileVertex { position: [0.06, 0.5, -0.06], normal: [0., 0., -1.] },
+    TileVertex { position: [0.06, -0.5, -0.06], normal: [0., 0., -1.] },
+    TileVertex { position: [-0.06, -0.5, -0.06], normal: [0., 0., -1.] },
+    TileVertex { position: [-0.06, 0.5, -0.06], normal: [0., 0., -1.] },
+
+    // Right
+    TileVertex { position: [0.06, 0.5, 0.06], normal: [1., 0., 0.] },
+    TileVertex { position: [0.06, -0.5, 0.06], normal: [1., 0., 0.] },
+    TileVertex { position: [0.06, -0.5, -0.06], normal: [1., 0., 0.] },
+    TileVertex { position: [0.06, 0.5, -0.06], normal: [1., 0., 0.] },
 
-// TODO: coalesce buffers (all have constant size)
+    // Left
+    TileVertex { position: [-0.06, 0.5, -0.06], normal: [-1., 0., 0.] },
+    TileVertex { position: [-0.06, -0.5, -0.06], normal: [-1., 0., 0.] },
+    TileVertex { position: [-0.06, -0.5, 0.06], normal: [-1., 0., 0.] },
+    TileVertex { position: [-0.06, 0.5, 0.06], normal: [-1., 0., 0.] },
+
+    // Top
+    TileVertex { position: [-0.06, 0.5, -0.06], normal: [0., 1., 0.] },
+    TileVertex { position: [-0.06, 0.5, 0.06], normal: [0., 1., 0.] },
+    TileVertex { position: [0.06, 0.5, 0.06], normal: [0., 1., 0.] },
+    TileVertex { position: [0.06, 0.5, -0.06], normal: [0., 1., 0.] },
+
+    // Bottom
+    TileVertex { position: [-0.06, -0.5, 0.06], normal: [0., -1., 0.] },
+    TileVertex { position: [-0.06, -0.5, -0.06], normal: [0., -1., 0.] },
+    TileVertex { position: [0.06, -0.5, -0.06], normal: [0., -1., 0.] },
+    TileVertex { position: [0.06, -0.5, 0.06], normal: [0., -1., 0.] },
+];
+
+// Same faces as the block
+const PEG_INDICES: &[u16] = BLOCK_INDICES;
+
+// TODO: coalesce buffers (all have constant size for a given board size)
 #[derive(Debug)]
 pub struct Board {
+    cols: usize,
+    rows: usize,
+
+    shape: Shape,
+
     board_pip: RenderPipeline,
+    // For boards that can hide themselves or tiles, unlike the flat one
+    depth_tested_pip: RenderPipeline,
     board_vertices: Buffer,
     board_indices: Buffer,
+    num_board_indices: u32,
+    // Angle the flat board is drawn turned through while a rotation plays
+    board_angle: f32,
 
     tile_pip: RenderPipeline,
     tile_vertices: Buffer,
     tile_indices: Buffer,
     tile_instances: Buffer,
-    num_tiles: usize,
+    // Instances of each mesh, which are stored in the order of `Mesh`
+    num_instances: [u32; 3],
 
     preview_rotation: UnitQuaternion<f32>,
 
-    preview: Option<u8>,
-    current_player: Tile,
-    win: Option<Tile>,
-    tiles: [[Option<Tile>; COLS]; ROWS]
+    // Cell under the mouse, with the preview drawn above its column unless there is no gravity
+    preview: Option<(usize, usize)>,
+    preview_power: Option<Power>,
+    // Colour of each player's discs
+    colors: [[f32; 4]; MAX_PLAYERS],
+    // Cell to draw flashing, such as a bomb's target
+    highlight: Option<(usize, usize)>,
+    view: View,
+    // Evaluation of playing in each column, drawn as a marker above it
+    hints: Vec<Option<Score>>,
+
+    // Number of moves in the game and its grid at the last prepare, to notice new pops
+    // and rotations
+    seen_moves: usize,
+    seen_grid: Grid,
+    pop: Option<PopAnimation>,
+    rotation: Option<RotationAnimation>
+}
+
+// Discs the viewer cannot see
+const HIDDEN_COLOR: [f32; 4] = [0.35, 0.35, 0.35, 1.];
+
+// Most blocks above a column in the hints, enough for the 21 moves a player has at most
+const MAX_HINT_MARKS: usize = 8;
+
+const POP_DURATION: Duration = Duration::from_millis(300);
+const TURN_DURATION: Duration = Duration::from_millis(400);
+const FALL_DURATION: Duration = Duration::from_millis(300);
+
+// A column sliding down after its bottom tile was popped out
+#[derive(Debug, Clone, Copy)]
+struct PopAnimation {
+    col: usize,
+    tile: Tile,
+    start: Instant
+}
+
+// The board turning a quarter turn, then its discs falling into place
+#[derive(Debug, Clone)]
+struct RotationAnimation {
+    rotation: Rotation,
+    // The grid as turned, before anything fell
+    turned: Grid,
+    start: Instant
+}
+
+// Red, yellow, green and blue
+const DEFAULT_COLORS: [[f32; 4]; MAX_PLAYERS] = [
+    [1., 0., 0., 1.],
+    [1., 1., 0., 1.],
+    [0., 0.8, 0., 1.],
+    [0.15, 0.45, 1., 1.]
+];
+
+fn mix(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+// Shades the owner's colour by the kind of disc
+fn disc_color(disc: Disc, color: [f32; 4]) -> [f32; 4] {
+    match disc.power {
+        None => color,
+        Some(Power::Anvil) => mix(color, [0.45, 0.47, 0.5, 1.], 0.6),
+        Some(Power::Bomb) => mix(color, [0., 0., 0., 1.], 0.7),
+        Some(Power::Wall) => [0.5, 0.5, 0.5, 1.],
+        Some(Power::Double) => mix(color, [1., 1., 1., 1.], 0.4)
+    }
+}
+
+// Whose discs are shown in fog of war
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    All,
+    // What one player can see, with the other discs drawn as placeholders
+    Player(Tile),
+    // Only placeholders, while the device is passed to the next player
+    Nothing
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Flat,
+    // Wrap-around boards bent round into a cylinder
+    Cylinder,
+    // Score Four pegs in a square with the given side
+    Pegs(usize)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mesh {
+    Hexagon,
+    Block,
+    Peg
+}
+
+impl Mesh {
+    const ALL: [Mesh; 3] = [Mesh::Hexagon, Mesh::Block, Mesh::Peg];
+
+    // Indices and base vertex in the shared tile buffers
+    fn range(self) -> (Range<u32>, i32) {
+        let (hexagon, block, peg) = (TILE_INDICES.len() as u32, BLOCK_INDICES.len() as u32, PEG_INDICES.len() as u32);
+        match self {
+            Mesh::Hexagon => (0..hexagon, 0),
+            Mesh::Block => (hexagon..hexagon + block, TILE_VERTICES.len() as i32),
+            Mesh::Peg => (hexagon + block..hexagon + block + peg, (TILE_VERTICES.len() + BLOCK_VERTICES.len()) as i32)
+        }
+    }
+}
+
+// Instances drawing a disc at the given place
+fn disc_instances(disc: Disc, model: Isometry3<f32>, color: [f32; 4]) -> Vec<(Mesh, TileInstance)> {
+    let instance = |model: Isometry3<f32>| TileInstance { model_mat: model.to_homogeneous(), color };
+    match disc.power {
+        Some(Power::Wall) => vec![(Mesh::Block, instance(model))],
+        // Two discs stacked front to back
+        Some(Power::Double) => [-0.06, 0.06].into_iter()
+            .map(|z| (Mesh::Hexagon, instance(model * Translation3::new(0., 0., z))))
+            .collect(),
+        _ => vec![(Mesh::Hexagon, instance(model))]
+    }
+}
+
+// Green for a win, white for a draw and red for a loss, fading the longer the game has to
+// go; estimates are pale
+fn hint_color(score: Score) -> [f32; 4] {
+    const GREY: [f32; 4] = [0.5, 0.5, 0.5, 1.];
+    const GREEN: [f32; 4] = [0., 0.9, 0., 1.];
+    const RED: [f32; 4] = [0.9, 0., 0., 1.];
+    let fade = |n: u32| (n.saturating_sub(1) as f32 / 21.).min(0.8);
+    match score {
+        Score::Exact(Evaluation::Win(n)) => mix(GREEN, GREY, fade(n)),
+        Score::Exact(Evaluation::Draw) => [1., 1., 1., 1.],
+        Score::Exact(Evaluation::Loss(n)) => mix(RED, GREY, fade(n)),
+        Score::Heuristic(score) => mix(GREY, if score > 0 { GREEN } else { RED }, (score.abs() as f32 / 1000.).min(0.4))
+    }
+}
+
+// Blocks above a column counting the moves to the end of a won or lost game, a large one
+// for every five and a small one for each of the rest, as (x, y, scale) from the centre of
+// the hint; draws and estimates get a single block
+fn hint_marks(score: Score) -> Vec<(f32, f32, f32)> {
+    let (Score::Exact(Evaluation::Win(n)) | Score::Exact(Evaluation::Loss(n))) = score else {
+        return vec![(0., 0., 0.35)];
+    };
+    let row = |count: u32, y: f32, scale: f32| (0..count).map(move |i| ((i as f32 - (count as f32 - 1.) / 2.) * 0.22, y, scale));
+    row(n / 5, 0.12, 0.25).chain(row(n % 5, -0.13, 0.15)).take(MAX_HINT_MARKS).collect()
 }
 
 fn smoothstep(x: f32, a: i32) -> f32 {
@@ -168,14 +481,14 @@ fn smoothstep(x: f32, a: i32) -> f32 {
 }
 
 impl Board {
-    pub fn new(dev: &Device, _q: &Queue, fmt: TextureFormat, camera_bgl: &BindGroupLayout) -> Self {
+    pub fn new(dev: &Device, _q: &Queue, fmt: TextureFormat, camera_bgl: &BindGroupLayout, cols: usize, rows: usize) -> Self {
         let board_shader = dev.create_shader_module(include_wgsl!("board.wgsl"));
         let board_ppl = dev.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &[camera_bgl],
             push_constant_ranges: &[]
         });
-        let board_pip = dev.create_render_pipeline(&RenderPipelineDescriptor {
+        let board_pipeline = |depth_compare| dev.create_render_pipeline(&RenderPipelineDescriptor {
             label: None,
             layout: Some(&board_ppl),
             vertex: VertexState {
@@ -205,11 +518,10 @@ impl Board {
                 polygon_mode: PolygonMode::Fill,
                 ..Default::default()
             },
-            // Always draw on top of everything
             depth_stencil: Some(DepthStencilState {
                 format: TextureFormat::Depth32Float,
                 depth_write_enabled: false,
-                depth_compare: CompareFunction::Always,
+                depth_compare,
                 stencil: Default::default(),
                 bias: Default::default()
             }),
@@ -217,17 +529,12 @@ impl Board {
             multiview: None,
             cache: None
         });
+        // The flat board always draws on top of everything, but the far side of a cylinder
+        // or the Score Four plate must not
+        let board_pip = board_pipeline(CompareFunction::Always);
+        let depth_tested_pip = board_pipeline(CompareFunction::Less);
 
-        let board_vertices = dev.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: cast_slice(BOARD_VERTICES),
-            usage: BufferUsages::VERTEX
-        });
-        let board_indices = dev.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: cast_slice(BOARD_INDICES),
-            usage: BufferUsages::INDEX
-        });
+        let (board_vertices, board_indices, num_board_indices) = Self::create_board_mesh(dev, cols, rows, Shape::Flat);
 
         let tile_shader = dev.create_shader_module(include_wgsl!("tile.wgsl"));
         let tile_ppl = dev.create_pipeline_layout(&PipelineLayoutDescriptor {
@@ -292,186 +599,399 @@ impl Board {
             cache: None
         });
 
+        // Hexagon, block and peg meshes
         let tile_vertices = dev.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            contents: cast_slice(TILE_VERTICES),
+            contents: cast_slice(&[TILE_VERTICES, BLOCK_VERTICES, PEG_VERTICES].concat()),
             usage: BufferUsages::VERTEX
         });
         let tile_indices = dev.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            contents: cast_slice(TILE_INDICES),
+            contents: cast_slice(&[TILE_INDICES, BLOCK_INDICES, PEG_INDICES].concat()),
             usage: BufferUsages::INDEX
         });
 
-        let tile_instances = dev.create_buffer(&BufferDescriptor {
+        let tile_instances = Self::create_tile_instances(dev, cols, rows);
+
+        Self {
+            cols, rows, shape: Shape::Flat,
+            board_pip, depth_tested_pip, board_vertices, board_indices, num_board_indices, board_angle: 0.,
+            tile_pip, tile_vertices, tile_indices, tile_instances,
+            preview_rotation: UnitQuaternion::identity(),
+            num_instances: [0; 3],
+            preview: None, preview_power: None, highlight: None, view: View::All, hints: Vec::new(),
+            colors: DEFAULT_COLORS,
+            seen_moves: 0, seen_grid: Grid::new(cols, rows), pop: None, rotation: None
+        }
+    }
+
+    fn create_board_mesh(dev: &Device, cols: usize, rows: usize, shape: Shape) -> (Buffer, Buffer, u32) {
+        let (vertices, indices) = match shape {
+            Shape::Flat => (board_vertices(cols, rows).to_vec(), BOARD_INDICES.to_vec()),
+            Shape::Cylinder => cylinder_mesh(cols, rows),
+            Shape::Pegs(side) => plate_mesh(side, rows)
+        };
+        let vertices = dev.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: cast_slice(&vertices),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST
+        });
+        let index_buf = dev.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            size: (mem::size_of::<TileInstance>()*(ROWS*COLS+1)) as u64,
+            contents: cast_slice(&indices),
+            usage: BufferUsages::INDEX
+        });
+        (vertices, index_buf, indices.len() as u32)
+    }
+
+    fn create_tile_instances(dev: &Device, cols: usize, rows: usize) -> Buffer {
+        // Every cell and a peg segment behind it, plus the preview, a popped tile, the
+        // second halves of double discs and the hint marks above each column
+        dev.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (mem::size_of::<TileInstance>()*(2*rows*cols+MAX_HINT_MARKS*cols+5)) as u64,
             usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
             mapped_at_creation: false
+        })
+    }
+
+    // Rebuilds the shape-dependent buffers, returning whether the shape changed
+    pub fn set_shape(&mut self, dev: &Device, cols: usize, rows: usize, shape: Shape) -> bool {
+        if (cols, rows, shape) == (self.cols, self.rows, self.shape) {
+            return false;
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.shape = shape;
+        (self.board_vertices, self.board_indices, self.num_board_indices) = Self::create_board_mesh(dev, cols, rows, shape);
+        self.board_angle = 0.;
+        self.tile_instances = Self::create_tile_instances(dev, cols, rows);
+        self.preview = None;
+        self.seen_grid = Grid::new(cols, rows);
+        self.pop = None;
+        self.rotation = None;
+        true
+    }
+
+    // Turns the flat board about its centre
+    fn turn_board(&mut self, q: &Queue, angle: f32) {
+        let turn = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), angle);
+        let vertices = board_vertices(self.cols, self.rows).map(|vertex| BoardVertex {
+            position: (turn * Point3::from(vertex.position)).coords.into(),
+            normal: (turn * Vector3::from(vertex.normal)).into(),
+            ..vertex
         });
+        q.write_buffer(&self.board_vertices, 0, cast_slice(&vertices));
+        self.board_angle = angle;
+    }
 
-        let tiles = Default::default();
+    fn half_cols(&self) -> f32 {
+        self.cols as f32 / 2.
+    }
 
-        // tiles[0][0] = Some(Tile::Red);
-        // tiles[1][2] = Some(Tile::Yellow);
+    fn half_rows(&self) -> f32 {
+        self.rows as f32 / 2.
+    }
 
-        Self {
-            board_pip, board_vertices, board_indices,
-            tile_pip, tile_vertices, tile_indices, tile_instances,
-            preview_rotation: UnitQuaternion::identity(),
-            tiles, num_tiles: 0, preview: None, current_player: Tile::Red, win: None
+    // Where a tile in the given column (counting from the left edge, so 0.5 is the centre of
+    // the first column) at height y is drawn
+    fn place(&self, col: f32, y: f32) -> Isometry3<f32> {
+        match self.shape {
+            Shape::Flat => Translation3::new(col - self.half_cols(), y, 0.).into(),
+            Shape::Cylinder => {
+                let angle = 2. * PI * col / self.cols as f32;
+                let radius = cylinder_radius(self.cols);
+                Isometry::from_parts(
+                    Translation3::new(radius * angle.sin(), y, radius * angle.cos()),
+                    UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle)
+                )
+            },
+            // Discs lie flat on their peg
+            Shape::Pegs(side) => {
+                let (x, z) = self.peg_position(col as usize, side);
+                Isometry::from_parts(
+                    Translation3::new(x, y, z),
+                    UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2)
+                )
+            }
         }
     }
 
-    fn column_from_ndc(x: f32, y: f32, camera: &mut Camera) -> Option<u8> {
-        // X and Y are NDC
-        // Sets self.preview to Some(0..7) if the mouse is over the board and None otherwise
-        // Uses raycasting to intersect with z=0 plane
+    // Centre of a peg in the x-z plane, with rows of pegs running towards the viewer
+    fn peg_position(&self, peg: usize, side: usize) -> (f32, f32) {
+        let half = side as f32 / 2.;
+        ((peg % side) as f32 - half + 0.5, (peg / side) as f32 - half + 0.5)
+    }
+
+    // X and Y are NDC
+    // Returns Some((row, column)) if the mouse is over the board and None otherwise
+    pub fn cell_from_ndc(&self, x: f32, y: f32, camera: &mut Camera) -> Option<(usize, usize)> {
+        // Uses raycasting to intersect with z=0 plane, the outside of the cylinder or the pegs
 
         let near = camera.unproject_point(&Point3::new(x, y, -1.));
         let far = camera.unproject_point(&Point3::new(x, y, 1.));
+        let (hit, col) = match self.shape {
+            Shape::Flat => self.column_on_plane(near, far)?,
+            Shape::Cylinder => self.column_on_cylinder(near, far)?,
+            Shape::Pegs(side) => self.peg_on_ray(near, far, side)?
+        };
+        let row = ((self.half_rows() - hit.y).max(0.) as usize).min(self.rows - 1);
+        Some((row, col))
+    }
 
+    fn column_on_plane(&self, near: Point3<f32>, far: Point3<f32>) -> Option<(Point3<f32>, usize)> {
         // Find point collinear to near and far such that z=0
         let dir = far - near;
         let t = -near.z / dir.z;
         let hit = near + dir * t;
 
-        if hit.x >= -HALF_COLS && hit.x <= HALF_COLS && hit.y >= -HALF_ROWS && hit.y <= HALF_ROWS {
-            // Convert x position to column index (0-6)
-            let col = (hit.x + HALF_COLS) as u8;
-            Some(col)
+        let (half_cols, half_rows) = (self.half_cols(), self.half_rows());
+        if hit.x >= -half_cols && hit.x <= half_cols && hit.y >= -half_rows && hit.y <= half_rows {
+            // Convert x position to column index
+            let col = ((hit.x + half_cols) as usize).min(self.cols - 1);
+            Some((hit, col))
         } else {
             None
         }
     }
 
-    pub fn set_preview(&mut self, x: f32, y: f32, camera: &mut Camera) {
-        self.preview = Self::column_from_ndc(x, y, camera);
-    }
+    fn column_on_cylinder(&self, near: Point3<f32>, far: Point3<f32>) -> Option<(Point3<f32>, usize)> {
+        // Nearest intersection of the ray with the outer wall, from x² + z² = r²
+        let r = cylinder_radius(self.cols) + CYLINDER_HALF_THICKNESS;
+        let dir = far - near;
+        let a = dir.x * dir.x + dir.z * dir.z;
+        let b = 2. * (near.x * dir.x + near.z * dir.z);
+        let c = near.x * near.x + near.z * near.z - r * r;
+        let disc = b * b - 4. * a * c;
+        if a == 0. || disc < 0. {
+            return None;
+        }
+        let t = (-b - disc.sqrt()) / (2. * a);
+        let hit = near + dir * t;
+        if t < 0. || hit.y.abs() > self.half_rows() {
+            return None;
+        }
 
-    fn find_win(&self) -> Option<Tile> {
-        // Generated by Copilot
+        let angle = hit.x.atan2(hit.z).rem_euclid(2. * PI);
+        let col = ((angle / (2. * PI) * self.cols as f32) as usize).min(self.cols - 1);
+        Some((hit, col))
+    }
 
-        // Check horizontal
-        for row in self.tiles.iter() {
-            for i in 0..=COLS-4 {
-                if let Some(tile) = row[i] {
-                    if row[i+1..i+4].iter().all(|&t| t == Some(tile)) {
-                        return Some(tile);
+    fn peg_on_ray(&self, near: Point3<f32>, far: Point3<f32>, side: usize) -> Option<(Point3<f32>, usize)> {
+        // Nearest hit on the box around each peg's column of cells, by the slab method
+        let dir = far - near;
+        let half_rows = self.half_rows();
+        let mut nearest = None;
+        for peg in 0..side * side {
+            let (x, z) = self.peg_position(peg, side);
+            let (min, max) = (Point3::new(x - 0.5, -half_rows, z - 0.5), Point3::new(x + 0.5, half_rows, z + 0.5));
+            let (mut enter, mut exit) = (0f32, 1f32);
+            for axis in 0..3 {
+                if dir[axis] == 0. {
+                    if near[axis] < min[axis] || near[axis] > max[axis] {
+                        exit = -1.;
                     }
+                    continue;
                 }
+                let (t0, t1) = ((min[axis] - near[axis]) / dir[axis], (max[axis] - near[axis]) / dir[axis]);
+                enter = enter.max(t0.min(t1));
+                exit = exit.min(t0.max(t1));
             }
-        }
-
-        // Check vertical
-        for i in 0..=COLS-1 {
-            for j in 0..=ROWS-4 {
-                if let Some(tile) = self.tiles[j][i] {
-                    if (0..4).all(|k| self.tiles[j+k][i] == Some(tile)) {
-                        return Some(tile);
-                    }
-                }
+            if enter <= exit && nearest.is_none_or(|(t, _)| enter < t) {
+                nearest = Some((enter, peg));
             }
         }
+        nearest.map(|(t, peg)| (near + dir * t, peg))
+    }
 
-        // Check diagonal
-        for i in 0..=COLS-4 {
-            for j in 0..=ROWS-4 {
-                if let Some(tile) = self.tiles[j][i] {
-                    if (0..4).all(|k| self.tiles[j+k][i+k] == Some(tile)) {
-                        return Some(tile);
-                    }
-                }
-            }
-        }
+    pub fn set_preview(&mut self, x: f32, y: f32, camera: &mut Camera) {
+        self.preview = self.cell_from_ndc(x, y, camera);
+    }
 
-        for i in 0..=COLS-4 {
-            for j in 3..=ROWS-1 {
-                if let Some(tile) = self.tiles[j][i] {
-                    if (0..4).all(|k| self.tiles[j-k][i+k] == Some(tile)) {
-                        return Some(tile);
-                    }
-                }
-            }
-        }
+    pub fn hide_preview(&mut self) {
+        self.preview = None;
+    }
+
+    pub fn preview(&self) -> Option<usize> {
+        self.preview.map(|(_, col)| col)
+    }
+
+    pub fn preview_cell(&self) -> Option<(usize, usize)> {
+        self.preview
+    }
+
+    pub fn set_color(&mut self, tile: Tile, color: [f32; 4]) {
+        self.colors[tile.id()] = color;
+    }
 
-        None
+    fn disc_color(&self, disc: Disc) -> [f32; 4] {
+        disc_color(disc, self.colors[disc.owner.id()])
     }
 
-    pub fn drop_tile(&mut self) {
-        if self.win.is_some() {
-            return;
+    // The kind of disc shown in the preview
+    pub fn set_preview_power(&mut self, power: Option<Power>) {
+        self.preview_power = power;
+    }
+
+    pub fn set_highlight(&mut self, cell: Option<(usize, usize)>) {
+        self.highlight = cell;
+    }
+
+    pub fn set_view(&mut self, view: View) {
+        self.view = view;
+    }
+
+    // Scores of the columns from left to right, or none to hide the hints
+    pub fn set_hints(&mut self, hints: Vec<Option<Score>>) {
+        self.hints = hints;
+    }
+
+    // Draws `editing` in place of the game's grid while a position is being set up
+    pub fn prepare(&mut self, q: &Queue, game: &Game, editing: Option<&Grid>) {
+        let mut tiles = Vec::new();
+        let half_rows = self.half_rows();
+        let t = 1. - ((2. * self.preview_rotation.angle() / PI + 1.) % 2. - 1.).abs();
+        let grid = editing.unwrap_or(game.grid());
+
+        let gravity = game.rules().has_gravity();
+        let preview = self.preview
+            .filter(|_| !game.is_over() && self.rotation.is_none() && editing.is_none() && self.view != View::Nothing)
+            .filter(|&(row, col)| gravity || !grid.is_occupied(row, col));
+        if let Some((row, col)) = preview {
+            // TODO: use OIT for preview and reenable alpha
+            // Spins about the vertical whichever way the disc is lying
+            let y = if gravity { half_rows + 1. } else { half_rows - 0.5 - row as f32 };
+            let place = self.place(col as f32 + 0.5, y);
+            let model = Isometry::from_parts(place.translation, self.preview_rotation * place.rotation);
+            let disc = Disc { owner: game.current_player(), power: self.preview_power };
+            let mut color = if disc.power.is_some() { self.disc_color(disc) } else { [0.5, 0.5, 0.5, 1.] };
+            color[3] = smoothstep(t, 2);
+            tiles.extend(disc_instances(disc, model, color));
         }
 
-        if let Some(col) = self.preview {
-            let col = col as usize;
-            for row in self.tiles.iter_mut().rev() {
-                if row[col].is_none() {
-                    row[col] = Some(self.current_player);
-                    self.current_player = match self.current_player {
-                        Tile::Red => Tile::Yellow,
-                        Tile::Yellow => Tile::Red
-                    };
-                    break;
+        self.preview_rotation = self.preview_rotation.append_axisangle_linearized(&(0.04f32 * Vector3::y()));
+
+        // Small blocks above the preview
+        if editing.is_none() {
+            for (col, score) in self.hints.iter().enumerate() {
+                if let Some(score) = *score {
+                    let place = self.place(col as f32 + 0.5, half_rows + 1.65);
+                    for (x, y, scale) in hint_marks(score) {
+                        let model = (place * Translation3::new(x, y, 0.)).to_homogeneous() * Matrix4::new_scaling(scale);
+                        tiles.push((Mesh::Block, TileInstance { model_mat: model, color: hint_color(score) }));
+                    }
                 }
             }
         }
 
-        if let Some(win) = self.find_win() {
-            self.win = Some(win);
-            println!("{:?} wins!", win);
-            return;
+        // Only animate pops made in play, not undos or jumps through the history
+        let moves = game.history().len();
+        if moves != self.seen_moves || editing.is_some() {
+            let played = moves == self.seen_moves + 1 && editing.is_none();
+            self.pop = match game.last_move() {
+                Some(Move::Pop(col)) if played => Some(PopAnimation {
+                    col,
+                    tile: game.last_mover().unwrap(),
+                    start: Instant::now()
+                }),
+                _ => None
+            };
+            self.rotation = match game.last_move() {
+                Some(Move::Rotate(rotation)) if played => Some(RotationAnimation {
+                    rotation,
+                    turned: self.seen_grid.rotated(rotation),
+                    start: Instant::now()
+                }),
+                _ => None
+            };
+            self.seen_moves = moves;
+            self.seen_grid = grid.clone();
         }
-    }
-
-    pub fn prepare(&mut self, q: &Queue) {
-        let mut inst_buf = q.write_buffer_with(&self.tile_instances, 0, NonZero::new(self.tile_instances.size()).unwrap()).unwrap();
-        let instances = cast_slice_mut(&mut inst_buf);
+        let pop = self.pop.map(|pop| (pop, pop.start.elapsed().as_secs_f32() / POP_DURATION.as_secs_f32()));
+        if pop.is_some_and(|(_, t)| t >= 1.) {
+            self.pop = None;
+        }
+        let (pop_col, shift) = match pop {
+            Some((pop, t)) if t < 1. => (Some(pop.col), 1. - smoothstep(t, 2)),
+            _ => (None, 0.)
+        };
 
-        let mut inst = 0;
+        // The angle the board has still to turn through, and how much of their fall the
+        // discs have still to make
+        let (angle, fall) = match &self.rotation {
+            Some(rotation) => {
+                let elapsed = rotation.start.elapsed();
+                let turned = (elapsed.as_secs_f32() / TURN_DURATION.as_secs_f32()).min(1.);
+                let fallen = (elapsed.saturating_sub(TURN_DURATION).as_secs_f32() / FALL_DURATION.as_secs_f32()).min(1.);
+                let sign = if rotation.rotation == Rotation::Clockwise { 1. } else { -1. };
+                (sign * FRAC_PI_2 * (1. - smoothstep(turned, 2)), 1. - smoothstep(fallen, 2))
+            },
+            None => (0., 0.)
+        };
+        if self.rotation.as_ref().is_some_and(|rotation| rotation.start.elapsed() >= TURN_DURATION + FALL_DURATION) {
+            self.rotation = None;
+        }
+        if angle != self.board_angle && self.shape == Shape::Flat {
+            self.turn_board(q, angle);
+        }
+        let turn = Isometry3::rotation(Vector3::z() * angle);
 
-        if let Some(preview) = self.preview {
-            // TODO: use OIT for preview and reenable alpha
-            let model = Isometry::from_parts(
-                Translation3::new(
-                    preview as f32 - HALF_COLS + 0.5,
-                    HALF_ROWS + 1.,
-                    0.
-                ),
-                self.preview_rotation
-            );
-            let t = 1. - ((2. * self.preview_rotation.angle() / PI + 1.) % 2. - 1.).abs();
-            let alpha = smoothstep(t, 2);
-            instances[inst] = TileInstance {
-                model_mat: model.to_homogeneous(),
-                color: [0.5, 0.5, 0.5, alpha]
+        for (i, j, disc) in grid.discs() {
+            // The popped column starts one row higher and falls into place
+            let offset = if Some(j) == pop_col { shift } else { 0. };
+            // After a rotation each disc falls from where it was turned to, keeping its
+            // order in its column
+            let row = match &self.rotation {
+                Some(rotation) => {
+                    let height = self.rows - 1 - i;
+                    let start = (0..self.rows).rev().filter(|&row| rotation.turned.is_occupied(row, j)).nth(height).unwrap_or(i);
+                    i as f32 + (start as f32 - i as f32) * fall
+                },
+                None => i as f32
+            };
+            let model = turn * self.place(j as f32 + 0.5, half_rows - 0.5 - row + offset);
+            let hidden = match self.view {
+                View::All => false,
+                View::Player(viewer) => !game.is_visible(i, j, viewer),
+                View::Nothing => true
             };
-            inst += 1;
+            if hidden {
+                tiles.extend(disc_instances(Disc::new(disc.owner), model, HIDDEN_COLOR));
+                continue;
+            }
+            let mut color = self.disc_color(disc);
+            if self.highlight == Some((i, j)) {
+                color = mix(color, [1., 1., 1., 1.], t);
+            }
+            tiles.extend(disc_instances(disc, model, color));
         }
 
-        self.preview_rotation = self.preview_rotation.append_axisangle_linearized(&(0.04f32 * Vector3::y()));
+        // The popped tile drops out of the bottom of the board
+        if let Some((pop, t)) = pop.filter(|&(_, t)| t < 1.) {
+            let model = self.place(pop.col as f32 + 0.5, -half_rows + 0.5 - (1. - shift) * 1.5);
+            let mut color = self.colors[pop.tile.id()];
+            color[3] = 1. - t;
+            tiles.extend(disc_instances(Disc::new(pop.tile), model, color));
+        }
 
-        for (i, row) in self.tiles.iter().enumerate() {
-            for (j, tile) in row.iter().enumerate() {
-                if let Some(tile) = tile {
-                    let model_mat = Translation3::new(
-                        j as f32 - HALF_COLS + 0.5,
-                        HALF_ROWS - 0.5 - i as f32,
-                        0.
-                    ).to_homogeneous();
-                    instances[inst] = TileInstance {
-                        model_mat,
-                        color: match tile {
-                            Tile::Red => [1., 0., 0., 1.],
-                            Tile::Yellow => [1., 1., 0., 1.]
-                        }
-                    };
-                    inst += 1;
+        // A segment of peg for every row, standing upright however the discs lie
+        if let Shape::Pegs(side) = self.shape {
+            for peg in 0..side * side {
+                let (x, z) = self.peg_position(peg, side);
+                for row in 0..self.rows {
+                    let model = Translation3::new(x, half_rows - 0.5 - row as f32, z).to_homogeneous();
+                    tiles.push((Mesh::Peg, TileInstance { model_mat: model, color: PEG_COLOR }));
                 }
             }
         }
 
-        self.num_tiles = inst;
+        let mut inst_buf = q.write_buffer_with(&self.tile_instances, 0, NonZero::new(self.tile_instances.size()).unwrap()).unwrap();
+        let instances: &mut [TileInstance] = cast_slice_mut(&mut inst_buf);
+        let mut slots = instances.iter_mut();
+        for (i, mesh) in Mesh::ALL.into_iter().enumerate() {
+            let meshes = tiles.iter().filter(|&&(m, _)| m == mesh);
+            self.num_instances[i] = slots.by_ref().zip(meshes).map(|(slot, &(_, instance))| *slot = instance).count() as u32;
+        }
     }
 
     pub fn render<'rpass>(&'rpass self, rpass: &mut RenderPass<'rpass>, camera_bg: &'rpass BindGroup) {
@@ -480,12 +1000,36 @@ impl Board {
         rpass.set_index_buffer(self.tile_indices.slice(..), IndexFormat::Uint16);
         rpass.set_vertex_buffer(1, self.tile_instances.slice(..));
         rpass.set_bind_group(0, camera_bg, &[]);
-        rpass.draw_indexed(0..TILE_INDICES.len() as u32, 0, 0..self.num_tiles as u32);
+        let mut first = 0;
+        for (mesh, count) in Mesh::ALL.into_iter().zip(self.num_instances) {
+            let (indices, base_vertex) = mesh.range();
+            rpass.draw_indexed(indices, base_vertex, first..first + count);
+            first += count;
+        }
         
-        rpass.set_pipeline(&self.board_pip);
+        rpass.set_pipeline(if self.shape == Shape::Flat { &self.board_pip } else { &self.depth_tested_pip });
         rpass.set_vertex_buffer(0, self.board_vertices.slice(..));
         rpass.set_index_buffer(self.board_indices.slice(..), IndexFormat::Uint16);
         rpass.set_bind_group(0, camera_bg, &[]);
-        rpass.draw_indexed(0..BOARD_INDICES.len() as u32, 0, 0..1);
+        rpass.draw_indexed(0..self.num_board_indices, 0, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hint_marks_count_moves() {
+        let large = |marks: &[(f32, f32, f32)]| marks.iter().filter(|&&(_, _, scale)| scale > 0.2).count();
+        let marks = hint_marks(Score::Exact(Evaluation::Win(13)));
+        assert_eq!((marks.len(), large(&marks)), (5, 2));
+        let marks = hint_marks(Score::Exact(Evaluation::Loss(1)));
+        assert_eq!((marks.len(), large(&marks)), (1, 0));
+        // Nineteen needs the most blocks
+        assert_eq!(hint_marks(Score::Exact(Evaluation::Win(19))).len(), 7);
+        assert_eq!(hint_marks(Score::Exact(Evaluation::Win(21))).len(), 5);
+        assert_eq!(hint_marks(Score::Exact(Evaluation::Draw)).len(), 1);
+        assert_eq!(hint_marks(Score::Heuristic(-40)).len(), 1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,270 @@
+// Opening book: solver scores for every position up to a number of plies
+//
+// File layout, all integers little-endian:
+//
+//   magic    b"C4BK"
+//   version  u8
+//   width    u8
+//   height   u8
+//   plies    u8
+//   count    u32
+//   entries  count * (key: u64, score: i8), sorted by key
+//
+// Keys are `Position::key` of whichever of the position and its mirror image
+// has the smaller key, so they do not depend on how the renderer lays out tiles.
+
+use std::{collections::HashSet, error::Error, fmt, fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread};
+
+use crate::{bitboard::{Position, HEIGHT, WIDTH}, solver::Solver};
+
+const MAGIC: &[u8; 4] = b"C4BK";
+const VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    BoardSize { width: u8, height: u8 },
+    Unsorted
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookError::Io(e) => write!(f, "{e}"),
+            BookError::BadMagic => write!(f, "not an opening book"),
+            BookError::UnsupportedVersion(v) => write!(f, "unsupported book version {v}"),
+            BookError::BoardSize { width, height } => write!(f, "book is for a {width}x{height} board, expected {WIDTH}x{HEIGHT}"),
+            BookError::Unsorted => write!(f, "book entries are not sorted")
+        }
+    }
+}
+
+impl Error for BookError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BookError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for BookError {
+    fn from(e: io::Error) -> Self {
+        BookError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    plies: u32,
+    entries: Vec<(u64, i8)>
+}
+
+impl Book {
+    // Solves every position reachable in at most `plies` moves, on `threads` threads
+    pub fn generate(plies: u32, threads: usize, progress: impl Fn(usize, usize) + Sync) -> Self {
+        let positions = positions_up_to(plies);
+        let total = positions.len();
+        let done = AtomicUsize::new(0);
+        let mut book = Arc::new(Self { plies, entries: Vec::new() });
+
+        // Deepest plies first, so that shallower searches end at book positions
+        for ply in (0..=plies).rev() {
+            let layer: Vec<_> = positions.iter().filter(|pos| pos.moves() == ply).collect();
+            let next = AtomicUsize::new(0);
+
+            let entries: Vec<(u64, i8)> = thread::scope(|s| {
+                let handles: Vec<_> = (0..threads.max(1))
+                    .map(|_| s.spawn(|| {
+                        let mut solver = Solver::with_book(book.clone());
+                        let mut entries = Vec::new();
+                        loop {
+                            let Some(pos) = layer.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                                break entries;
+                            };
+                            entries.push((pos.canonical_key(), solver.solve(pos) as i8));
+                            progress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+                        }
+                    }))
+                    .collect();
+                handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+            });
+
+            let book = Arc::get_mut(&mut book).unwrap();
+            book.entries.extend(entries);
+            book.entries.sort_unstable_by_key(|&(key, _)| key);
+        }
+
+        Arc::into_inner(book).unwrap()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BookError> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn read(mut r: impl Read) -> Result<Self, BookError> {
+        let mut header = [0; 12];
+        r.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(BookError::BadMagic);
+        }
+        if header[4] != VERSION {
+            return Err(BookError::UnsupportedVersion(header[4]));
+        }
+        let (width, height) = (header[5], header[6]);
+        if width as usize != WIDTH || height as usize != HEIGHT {
+            return Err(BookError::BoardSize { width, height });
+        }
+        let plies = header[7] as u32;
+        let count = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+
+        // Not preallocated from the count, which a damaged file could set to anything
+        let mut entries = Vec::new();
+        let mut entry = [0; 9];
+        for _ in 0..count {
+            r.read_exact(&mut entry)?;
+            let key = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+            entries.push((key, entry[8] as i8));
+        }
+        if !entries.is_sorted_by_key(|&(key, _)| key) {
+            return Err(BookError::Unsorted);
+        }
+
+        Ok(Self { plies, entries })
+    }
+
+    pub fn write(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION, WIDTH as u8, HEIGHT as u8, self.plies as u8])?;
+        w.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        for &(key, score) in &self.entries {
+            w.write_all(&key.to_le_bytes())?;
+            w.write_all(&[score as u8])?;
+        }
+        Ok(())
+    }
+
+    // Deepest ply covered by the book
+    pub fn plies(&self) -> u32 {
+        self.plies
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, pos: &Position) -> Option<i32> {
+        if pos.moves() > self.plies {
+            return None;
+        }
+        let key = pos.canonical_key();
+        self.entries.binary_search_by_key(&key, |&(key, _)| key)
+            .ok()
+            .map(|i| self.entries[i].1 as i32)
+    }
+}
+
+// Unique (up to mirroring) positions without a winner, at most `plies` moves deep
+fn positions_up_to(plies: u32) -> Vec<Position> {
+    let mut seen = HashSet::new();
+    let mut positions = Vec::new();
+    let mut stack = vec![Position::new()];
+    while let Some(pos) = stack.pop() {
+        if pos.last_move_won() || !seen.insert(pos.canonical_key()) {
+            continue;
+        }
+        positions.push(pos);
+        if pos.moves() < plies {
+            for col in 0..WIDTH {
+                if pos.can_play(col) {
+                    let mut next = pos;
+                    next.play(col);
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> Book {
+        let mut pos = Position::new();
+        let mut entries = vec![(pos.canonical_key(), 1)];
+        pos.play(0);
+        entries.push((pos.canonical_key(), -2));
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        Book { plies: 1, entries }
+    }
+
+    fn bytes(book: &Book) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        book.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let book = book();
+        let read = Book::read(bytes(&book).as_slice()).unwrap();
+        assert_eq!(read.plies(), 1);
+        assert_eq!(read.entries, book.entries);
+
+        let mut pos = Position::new();
+        assert_eq!(read.get(&pos), Some(1));
+        pos.play(WIDTH - 1);
+        assert_eq!(read.get(&pos), Some(-2));
+        pos.play(0);
+        assert_eq!(read.get(&pos), None);
+    }
+
+    #[test]
+    fn truncated_file() {
+        let bytes = bytes(&book());
+        for len in [0, 11, 12, bytes.len() - 1] {
+            assert!(matches!(Book::read(&bytes[..len]), Err(BookError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+        }
+    }
+
+    #[test]
+    fn huge_count() {
+        let mut bytes = bytes(&book());
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Book::read(bytes.as_slice()), Err(BookError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = bytes(&book());
+        bytes[4] = 9;
+        assert!(matches!(Book::read(bytes.as_slice()), Err(BookError::UnsupportedVersion(9))));
+        bytes[5] = 8;
+        bytes[4] = VERSION;
+        assert!(matches!(Book::read(bytes.as_slice()), Err(BookError::BoardSize { width: 8, height: 6 })));
+        bytes[0] = b'X';
+        assert!(matches!(Book::read(bytes.as_slice()), Err(BookError::BadMagic)));
+    }
+
+    #[test]
+    fn unsorted_entries() {
+        let mut book = book();
+        book.entries.reverse();
+        assert!(matches!(Book::read(bytes(&book).as_slice()), Err(BookError::Unsorted)));
+    }
+}
@@ -71,6 +71,23 @@ impl Camera {
         self.needs_update = true;
     }
 
+    // Moves the eye back far enough to see a board of the given size
+    pub fn fit_board(&mut self, cols: usize, rows: usize) {
+        self.eye.y = 0.;
+        self.eye.z = 12. * (cols as f32 / 7.).max(rows as f32 / 6.).max(1.);
+        self.proj.set_zfar(100f32.max(self.eye.z * 4.));
+        self.needs_update = true;
+    }
+
+    // Looks down at Score Four pegs from above so that every peg can be seen
+    pub fn fit_pegs(&mut self, side: usize, rows: usize) {
+        let distance = 2.5 * side.max(rows) as f32;
+        self.eye.y = 0.6 * distance;
+        self.eye.z = distance;
+        self.proj.set_zfar(100f32.max(distance * 4.));
+        self.needs_update = true;
+    }
+
     pub fn set_aspect(&mut self, aspect: f32) {
         self.proj.set_aspect(aspect);
         self.needs_update = true;
@@ -78,7 +95,7 @@ impl Camera {
 
     fn update_view_proj(&mut self) {
         let rot = UnitQuaternion::from_axis_angle(&self.up, self.angle);
-        let eye = Isometry3::rotation_wrt_point(rot, self.target) * &self.eye;
+        let eye = Isometry3::rotation_wrt_point(rot, self.target) * self.eye;
         self.view = Isometry3::look_at_rh(&eye, &self.target, &self.up);
         self.cached_view_proj = self.proj.as_matrix() * self.view.to_homogeneous();
         self.needs_update = false;
@@ -102,7 +119,7 @@ impl Camera {
         &self.bgl
     }
 
-    pub fn bind_group<'a>(&mut self, q: &'a Queue) -> &BindGroup {
+    pub fn bind_group(&mut self, q: &Queue) -> &BindGroup {
         let view_proj = self.view_proj();
         let mut view = q.write_buffer_with(&self.buf, 0, NonZeroU64::new(self.buf.size()).unwrap()).unwrap();
         *from_bytes_mut(&mut view) = view_proj;
@@ -0,0 +1,68 @@
+use c4::game::{Disc, Game, Grid, Power, Rules, SetupError, Tile};
+
+// A position being set up by hand, to play on from
+#[derive(Debug, Clone)]
+pub struct Editor {
+    grid: Grid,
+    to_move: Tile,
+    // Power discs already used stay used
+    spent: Vec<(Tile, Power)>
+}
+
+impl Editor {
+    // Starts from the game's current position
+    pub fn new(game: &Game) -> Self {
+        Self { grid: game.grid().clone(), to_move: game.current_player(), spent: game.spent_powers() }
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn to_move(&self) -> Tile {
+        self.to_move
+    }
+
+    // Cycles a cell through empty and each player's disc in turn order
+    pub fn cycle_cell(&mut self, row: usize, col: usize, players: usize) {
+        let owner = match self.grid.disc(row, col) {
+            None => Some(Tile::RED),
+            Some(disc) if disc.owner.id() + 1 < players => Some(disc.owner.next(players)),
+            Some(_) => None
+        };
+        self.grid.set_disc(row, col, owner.map(Disc::new));
+    }
+
+    pub fn cycle_to_move(&mut self, players: usize) {
+        self.to_move = self.to_move.next(players);
+    }
+
+    // A game from the position, if it is one that can be played on from
+    pub fn finish(&self, rules: Rules) -> Result<Game, SetupError> {
+        Ok(Game::from_position(rules, self.grid.clone(), self.to_move)?.with_spent_powers(&self.spent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use c4::game::{Move, MoveError, Variant};
+
+    use super::*;
+
+    #[test]
+    fn finishing_keeps_the_used_powers() {
+        let rules = Rules::STANDARD.with_variant(Variant::PowerUp).unwrap();
+        let mut game = Game::new(rules);
+        game.play_move(Move::Power(Power::Wall, 3)).unwrap();
+        game.play_move(Move::Power(Power::Anvil, 4)).unwrap();
+
+        let mut editor = Editor::new(&game);
+        editor.cycle_cell(5, 0, rules.players);
+        let edited = editor.finish(rules).unwrap();
+        assert_eq!(edited.grid().disc(5, 0), Some(Disc::new(Tile::RED)));
+        assert_eq!(edited.spent_powers(), vec![(Tile::RED, Power::Wall), (Tile::YELLOW, Power::Anvil)]);
+        assert!(!edited.has_power(Tile::RED, Power::Wall));
+        assert!(edited.has_power(Tile::RED, Power::Anvil));
+        assert_eq!(edited.check_move(Move::Power(Power::Wall, 0)), Err(MoveError::PowerUsed(Power::Wall)));
+    }
+}
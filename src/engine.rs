@@ -0,0 +1,640 @@
+// Line-based protocol for Connect Four engines running as separate programs, in the
+// spirit of UCI. The game writes commands to the engine's stdin and reads replies from
+// its stdout:
+//
+//   > c4engine
+//   < id name Example
+//   < id author Someone
+//   < c4engineok
+//   > position rules 7x6 4 Standard 2 Normal moves 4 4 3
+//   > go movetime 1000
+//   < info depth 8 score win 5 nodes 20311 pv 3 3
+//   < bestmove 3
+//   > quit
+//
+// The engine answers "c4engine" with optional id lines, then "c4engineok". Positions give
+// the rules as size, connect length, variant, number of players and scoring, then the
+// moves played so far in record notation. Games set up by hand also give the position
+// play started from before the moves, as in "start ......./.../...12.. 1", with rows from
+// the top separated by "/", "." for an empty cell or the owner's number counted from 1
+// followed by the first letter of its power if it has one, as in "1w", and the number of
+// the player to move. Power discs already used by then follow as "spent 1a 2w". "go" may limit the search with "movetime MS",
+// "depth N", and the time left on the engine's clock with "time MS" and "inc MS"; the
+// engine sends any number of info lines and then exactly one bestmove, which is
+// "bestmove none" if the game is over or the position cannot be played. "stop" asks for
+// the best move found so far. An engine that takes much longer than its move time to
+// answer "go" is given up on. Scores are from the point of view of the player to move:
+// "win N" and "loss N" when the winner drops their last disc as their N-th from now,
+// "draw", or "cp N" for an estimate in the engine's own units. Lines that cannot be
+// understood are ignored by both sides.
+
+use std::{error::Error, ffi::OsStr, fmt, io::{self, BufRead, BufReader, Write}, process::{self, Child, ChildStdin, Stdio}, str::FromStr, sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError}, thread, time::{Duration, Instant}};
+
+use crate::{game::{Disc, Game, Grid, MAX_PLAYERS, Move, Power, Rules, Tile}, player::{Player, PlayerError}, solver::Evaluation};
+
+// How long an engine has to answer "c4engine"
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Extra time past its move time an engine has to answer "go"
+pub const GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    pub movetime: Option<Duration>,
+    pub depth: Option<u32>,
+    // Time left on the engine's clock and added after each of its moves
+    pub time: Option<Duration>,
+    pub inc: Option<Duration>
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(movetime) = self.movetime {
+            fields.push(format!("movetime {}", movetime.as_millis()));
+        }
+        if let Some(depth) = self.depth {
+            fields.push(format!("depth {depth}"));
+        }
+        if let Some(time) = self.time {
+            fields.push(format!("time {}", time.as_millis()));
+        }
+        if let Some(inc) = self.inc {
+            fields.push(format!("inc {}", inc.as_millis()));
+        }
+        f.write_str(&fields.join(" "))
+    }
+}
+
+// A position set up by hand: the grid, the player to move and the power discs used by then
+pub type Start = (Grid, Tile, Vec<(Tile, Power)>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Hello,
+    Position { rules: Rules, start: Option<Start>, moves: Vec<Move> },
+    Go(Limits),
+    Stop,
+    Quit
+}
+
+impl Command {
+    // The game's position, with its start left out if it is the empty board
+    pub fn position(game: &Game) -> Self {
+        let (grid, to_move, spent) = game.start();
+        let start = (grid.discs().next().is_some() || to_move != Tile::RED || !spent.is_empty())
+            .then(|| (grid.clone(), to_move, spent));
+        Command::Position { rules: game.rules(), start, moves: game.history().to_vec() }
+    }
+
+    // The game the position describes, if its moves can be played
+    pub fn to_game(&self) -> Option<Game> {
+        let Command::Position { rules, start, moves } = self else {
+            return None;
+        };
+        let mut game = match start {
+            Some((grid, to_move, spent)) => Game::from_position(*rules, grid.clone(), *to_move).ok()?.with_spent_powers(spent),
+            None => Game::new(*rules)
+        };
+        for &mv in moves {
+            game.play_move(mv).ok()?;
+        }
+        Some(game)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Hello => write!(f, "c4engine"),
+            Command::Position { rules, start, moves } => {
+                write!(
+                    f, "position rules {}x{} {} {} {} {}",
+                    rules.cols, rules.rows, rules.connect, rules.variant, rules.players, rules.scoring
+                )?;
+                if let Some((grid, to_move, spent)) = start {
+                    write!(f, " start {} {}", grid_string(grid), to_move.id() + 1)?;
+                    if !spent.is_empty() {
+                        write!(f, " spent")?;
+                    }
+                    for (tile, power) in spent {
+                        write!(f, " {}{}", tile.id() + 1, power_letter(*power))?;
+                    }
+                }
+                write!(f, " moves")?;
+                for mv in moves {
+                    write!(f, " {mv}")?;
+                }
+                Ok(())
+            },
+            Command::Go(limits) if *limits == Limits::default() => write!(f, "go"),
+            Command::Go(limits) => write!(f, "go {limits}"),
+            Command::Stop => write!(f, "stop"),
+            Command::Quit => write!(f, "quit")
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+        let command = match tokens.next().ok_or(())? {
+            "c4engine" => Command::Hello,
+            "position" => parse_position(&mut tokens)?,
+            "go" => {
+                let mut limits = Limits::default();
+                while let Some(key) = tokens.next() {
+                    match key {
+                        "movetime" => limits.movetime = Some(Duration::from_millis(next(&mut tokens)?)),
+                        "depth" => limits.depth = Some(next(&mut tokens)?),
+                        "time" => limits.time = Some(Duration::from_millis(next(&mut tokens)?)),
+                        "inc" => limits.inc = Some(Duration::from_millis(next(&mut tokens)?)),
+                        _ => return Err(())
+                    }
+                }
+                Command::Go(limits)
+            },
+            "stop" => Command::Stop,
+            "quit" => Command::Quit,
+            _ => return Err(())
+        };
+        Ok(command)
+    }
+}
+
+fn next<'a, T: FromStr>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<T, ()> {
+    tokens.next().ok_or(())?.parse().map_err(|_| ())
+}
+
+fn parse_position<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Command, ()> {
+    if tokens.next() != Some("rules") {
+        return Err(());
+    }
+    let (cols, rows) = tokens.next().and_then(|size| size.split_once('x')).ok_or(())?;
+    let (cols, rows) = (cols.parse().map_err(|_| ())?, rows.parse().map_err(|_| ())?);
+    let connect = next(tokens)?;
+    let (variant, players, scoring) = (next(tokens)?, next(tokens)?, next(tokens)?);
+    let rules = Rules::from_parts(cols, rows, connect, variant, players, scoring).map_err(|_| ())?;
+
+    let start = match tokens.next() {
+        Some("start") => {
+            let grid = parse_grid(tokens.next().ok_or(())?, rules)?;
+            let to_move = player(tokens.next().ok_or(())?)?;
+            let mut spent = Vec::new();
+            match tokens.next() {
+                Some("spent") => loop {
+                    match tokens.next().ok_or(())? {
+                        "moves" => break,
+                        token => {
+                            let (id, letter) = token.split_at_checked(1).ok_or(())?;
+                            spent.push((player(id)?, parse_power(letter)?));
+                        }
+                    }
+                },
+                Some("moves") => {},
+                _ => return Err(())
+            }
+            Some((grid, to_move, spent))
+        },
+        Some("moves") => None,
+        _ => return Err(())
+    };
+    let moves = tokens.map(str::parse).collect::<Result<_, _>>()?;
+    Ok(Command::Position { rules, start, moves })
+}
+
+// A player's number counted from 1
+fn player(s: &str) -> Result<Tile, ()> {
+    match s.parse::<usize>() {
+        Ok(id @ 1..=MAX_PLAYERS) => Ok(Tile::new(id - 1)),
+        _ => Err(())
+    }
+}
+
+// As in move notation
+fn power_letter(power: Power) -> String {
+    power.to_string()[..1].to_owned()
+}
+
+fn parse_power(s: &str) -> Result<Power, ()> {
+    Power::ALL.into_iter().find(|&power| power_letter(power) == s).ok_or(())
+}
+
+fn grid_string(grid: &Grid) -> String {
+    let rows: Vec<String> = (0..grid.rows())
+        .map(|row| (0..grid.cols())
+            .map(|col| match grid.disc(row, col) {
+                Some(disc) => format!("{}{}", disc.owner.id() + 1, disc.power.map(power_letter).unwrap_or_default()),
+                None => ".".to_owned()
+            })
+            .collect())
+        .collect();
+    rows.join("/")
+}
+
+fn parse_grid(s: &str, rules: Rules) -> Result<Grid, ()> {
+    let mut grid = Grid::new(rules.cols, rules.rows);
+    let rows: Vec<_> = s.split('/').collect();
+    if rows.len() != rules.rows {
+        return Err(());
+    }
+    for (row, cells) in rows.into_iter().enumerate() {
+        let mut chars = cells.chars().peekable();
+        let mut col = 0;
+        while let Some(cell) = chars.next() {
+            if col == rules.cols {
+                return Err(());
+            }
+            if cell != '.' {
+                let owner = player(&cell.to_string())?;
+                let power = match chars.next_if(char::is_ascii_lowercase) {
+                    Some(letter) => Some(parse_power(&letter.to_string())?),
+                    None => None
+                };
+                grid.set_disc(row, col, Some(Disc { owner, power }));
+            }
+            col += 1;
+        }
+        if col != rules.cols {
+            return Err(());
+        }
+    }
+    Ok(grid)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Exact(Evaluation),
+    Heuristic(i32)
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Score::Exact(Evaluation::Win(n)) => write!(f, "win {n}"),
+            Score::Exact(Evaluation::Draw) => write!(f, "draw"),
+            Score::Exact(Evaluation::Loss(n)) => write!(f, "loss {n}"),
+            Score::Heuristic(score) => write!(f, "cp {score}")
+        }
+    }
+}
+
+// What an engine reports about its search
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Info {
+    pub depth: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    // The moves it expects, starting with its own
+    pub pv: Vec<Move>
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(depth) = self.depth {
+            fields.push(format!("depth {depth}"));
+        }
+        if let Some(score) = self.score {
+            fields.push(format!("score {score}"));
+        }
+        if let Some(nodes) = self.nodes {
+            fields.push(format!("nodes {nodes}"));
+        }
+        if !self.pv.is_empty() {
+            let pv: Vec<_> = self.pv.iter().map(Move::to_string).collect();
+            fields.push(format!("pv {}", pv.join(" ")));
+        }
+        f.write_str(&fields.join(" "))
+    }
+}
+
+fn parse_info<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Info, ()> {
+    let mut info = Info::default();
+    while let Some(key) = tokens.next() {
+        match key {
+            "depth" => info.depth = Some(next(tokens)?),
+            "nodes" => info.nodes = Some(next(tokens)?),
+            "score" => info.score = Some(match tokens.next().ok_or(())? {
+                "win" => Score::Exact(Evaluation::Win(next(tokens)?)),
+                "draw" => Score::Exact(Evaluation::Draw),
+                "loss" => Score::Exact(Evaluation::Loss(next(tokens)?)),
+                "cp" => Score::Heuristic(next(tokens)?),
+                _ => return Err(())
+            }),
+            // The principal variation runs to the end of the line
+            "pv" => info.pv = tokens.by_ref().map(str::parse).collect::<Result<_, _>>()?,
+            _ => return Err(())
+        }
+    }
+    Ok(info)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Name(String),
+    Author(String),
+    HelloOk,
+    Info(Info),
+    // None when there is no move to play
+    BestMove(Option<Move>)
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Name(name) => write!(f, "id name {name}"),
+            Response::Author(author) => write!(f, "id author {author}"),
+            Response::HelloOk => write!(f, "c4engineok"),
+            Response::Info(info) => write!(f, "info {info}"),
+            Response::BestMove(Some(mv)) => write!(f, "bestmove {mv}"),
+            Response::BestMove(None) => write!(f, "bestmove none")
+        }
+    }
+}
+
+impl FromStr for Response {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+        let response = match tokens.next().ok_or(())? {
+            "id" => {
+                let key = tokens.next().ok_or(())?;
+                let value = tokens.collect::<Vec<_>>().join(" ");
+                match key {
+                    "name" => Response::Name(value),
+                    "author" => Response::Author(value),
+                    _ => return Err(())
+                }
+            },
+            "c4engineok" => Response::HelloOk,
+            "info" => Response::Info(parse_info(&mut tokens)?),
+            "bestmove" => match tokens.next().ok_or(())? {
+                "none" => Response::BestMove(None),
+                mv => Response::BestMove(Some(mv.parse()?))
+            },
+            _ => return Err(())
+        };
+        Ok(response)
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    Io(io::Error),
+    // No "c4engineok" in reply to "c4engine"
+    Handshake,
+    Exited,
+    // "bestmove none" for a game that is still going
+    NoMove,
+    Timeout
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Io(e) => write!(f, "{e}"),
+            EngineError::Handshake => write!(f, "the engine did not answer c4engine"),
+            EngineError::Exited => write!(f, "the engine exited"),
+            EngineError::NoMove => write!(f, "the engine found no move"),
+            EngineError::Timeout => write!(f, "the engine took too long to move")
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        EngineError::Io(e)
+    }
+}
+
+// An engine program playing one colour
+#[derive(Debug)]
+pub struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    // Lines from the engine's stdout, read on their own thread so polling never waits
+    replies: Receiver<String>,
+    name: String,
+    limits: Limits,
+    // The position being searched, if any, and when the engine must have answered by
+    searching: Option<Command>,
+    deadline: Option<Instant>,
+    stopping: bool,
+    info: Option<Info>
+}
+
+impl Engine {
+    pub fn spawn<I, S>(program: impl AsRef<OsStr>, args: I, limits: Limits) -> Result<Self, EngineError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>
+    {
+        let mut child = process::Command::new(program.as_ref())
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let name = program.as_ref().to_string_lossy().into_owned();
+        let mut engine = Self { child, stdin, replies, name, limits, searching: None, deadline: None, stopping: false, info: None };
+        engine.send(&Command::Hello)?;
+        loop {
+            let line = match engine.replies.recv_timeout(HANDSHAKE_TIMEOUT) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err(EngineError::Handshake),
+                Err(RecvTimeoutError::Disconnected) => return Err(EngineError::Exited)
+            };
+            match line.parse() {
+                Ok(Response::Name(name)) => engine.name = name,
+                Ok(Response::HelloOk) => return Ok(engine),
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, command: &Command) -> io::Result<()> {
+        writeln!(self.stdin, "{command}")
+    }
+}
+
+impl Player for Engine {
+    fn name(&self) -> String {
+        format!("Engine ({})", self.name)
+    }
+
+    fn poll_move(&mut self, game: &Game) -> Result<Option<Move>, PlayerError> {
+        let position = Command::position(game);
+        match &self.searching {
+            None => {
+                self.send(&position)?;
+                self.send(&Command::Go(self.limits))?;
+                self.searching = Some(position.clone());
+                self.deadline = self.limits.movetime.map(|movetime| Instant::now() + movetime + GRACE);
+                self.info = None;
+            },
+            // The game was changed under the engine, so its move will be thrown away
+            Some(searched) if *searched != position && !self.stopping => {
+                self.send(&Command::Stop)?;
+                self.stopping = true;
+            },
+            Some(_) => {}
+        }
+
+        loop {
+            let line = match self.replies.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) if self.deadline.is_some_and(|deadline| Instant::now() > deadline) => {
+                    return Err(EngineError::Timeout.into());
+                },
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(EngineError::Exited.into())
+            };
+            match line.parse() {
+                Ok(Response::Info(info)) => self.info = Some(info),
+                Ok(Response::BestMove(mv)) => {
+                    self.stopping = false;
+                    if self.searching.take() != Some(position) {
+                        return Ok(None);
+                    }
+                    return mv.map(Some).ok_or(EngineError::NoMove.into());
+                },
+                Err(()) if line.starts_with("bestmove") => return Err(PlayerError::BadMessage(line)),
+                _ => {}
+            }
+        }
+    }
+
+    fn info(&self) -> Option<&Info> {
+        self.info.as_ref()
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send(&Command::Quit);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Variant;
+
+    // An engine program written in shell, answering "go" with `reply` if it is given
+    fn fake_engine(reply: Option<&str>) -> Engine {
+        let script = match reply {
+            Some(reply) => format!("echo c4engineok; while read line; do case $line in go*) echo {reply};; esac; done"),
+            None => "echo c4engineok; while read line; do :; done".to_owned()
+        };
+        let limits = Limits { movetime: Some(Duration::from_millis(1)), ..Limits::default() };
+        Engine::spawn("sh", ["-c", &script], limits).unwrap()
+    }
+
+    fn poll(engine: &mut Engine, game: &Game) -> Result<Option<Move>, PlayerError> {
+        loop {
+            match engine.poll_move(game) {
+                Ok(None) => thread::sleep(Duration::from_millis(1)),
+                result => return result
+            }
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let info = Info { depth: Some(8), score: Some(Score::Exact(Evaluation::Win(5))), nodes: Some(20311), pv: vec![Move::Drop(2), Move::Drop(2)] };
+        for response in [
+            Response::Name("Example engine".to_owned()),
+            Response::HelloOk,
+            Response::Info(info),
+            Response::BestMove(Some(Move::Drop(2))),
+            Response::BestMove(None)
+        ] {
+            assert_eq!(response.to_string().parse(), Ok(response));
+        }
+        assert_eq!("bestmove".parse::<Response>(), Err(()));
+        assert_eq!("bestmove x".parse::<Response>(), Err(()));
+    }
+
+    #[test]
+    fn position_round_trip() {
+        let mut game = Game::default();
+        for col in [3, 3, 2] {
+            game.play(col).unwrap();
+        }
+        let command = Command::position(&game);
+        assert_eq!(command.to_string(), "position rules 7x6 4 Standard 2 Normal moves 4 4 3");
+        let parsed: Command = command.to_string().parse().unwrap();
+        assert_eq!(parsed.to_game().unwrap().grid(), game.grid());
+    }
+
+    #[test]
+    fn power_position_round_trip() {
+        let rules = Rules::STANDARD.with_variant(Variant::PowerUp).unwrap();
+        let mut played = Game::new(rules);
+        played.play_move(Move::Power(Power::Wall, 3)).unwrap();
+        played.play(3).unwrap();
+        let mut game = Game::from_position(rules, played.grid().clone(), Tile::RED).unwrap()
+            .with_spent_powers(&played.spent_powers());
+        game.play(0).unwrap();
+
+        let command = Command::position(&game);
+        assert_eq!(
+            command.to_string(),
+            "position rules 7x6 4 PowerUp 2 Normal start ......./......./......./......./...2.../...1w... 1 spent 1w moves 1"
+        );
+        let parsed = command.to_string().parse::<Command>().unwrap().to_game().unwrap();
+        assert_eq!(parsed.grid(), game.grid());
+        assert_eq!(parsed.start(), game.start());
+        assert!(!parsed.has_power(Tile::RED, Power::Wall));
+
+        let bad = "position rules 7x6 4 PowerUp 2 Normal start ......./......./......./......./......./...1x.. 1 moves";
+        assert_eq!(bad.parse::<Command>(), Err(()));
+        let bad = "position rules 7x6 4 PowerUp 2 Normal start ......./......./......./......./......./...1... 1 spent 5a moves";
+        assert_eq!(bad.parse::<Command>(), Err(()));
+    }
+
+    #[test]
+    fn engine_moves() {
+        let mut engine = fake_engine(Some("bestmove 4"));
+        assert_eq!(poll(&mut engine, &Game::default()).unwrap(), Some(Move::Drop(3)));
+    }
+
+    #[test]
+    fn engine_without_a_move() {
+        let mut engine = fake_engine(Some("bestmove none"));
+        assert!(matches!(poll(&mut engine, &Game::default()), Err(PlayerError::Engine(EngineError::NoMove))));
+    }
+
+    #[test]
+    fn engine_out_of_time() {
+        let mut engine = fake_engine(None);
+        assert!(matches!(poll(&mut engine, &Game::default()), Err(PlayerError::Engine(EngineError::Timeout))));
+    }
+}
@@ -0,0 +1,1778 @@
+// Game rules, independent of the renderer
+
+use std::{error::Error, fmt, iter, hash::{DefaultHasher, Hash, Hasher}, str::FromStr};
+
+// Largest supported board side, which keeps the renderer's buffers small
+pub const MAX_SIZE: usize = 32;
+
+pub const MAX_PLAYERS: usize = 4;
+
+// Positions seen this many times are drawn in PopOut and Gravity Flip
+const REPETITION_DRAW: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    // Players may also remove one of their own tiles from the bottom row
+    PopOut,
+    // The left and right edges are joined, so lines can wrap around
+    Cylinder,
+    // Each player also has one anvil, bomb, wall and double disc
+    PowerUp,
+    // Columns are pegs in a square, with lines running in three dimensions
+    ScoreFour,
+    // Players may also turn the square board a quarter turn, letting every disc fall
+    GravityFlip,
+    // There is no gravity, so discs go in any empty cell
+    FreePlacement,
+    // Players only see their own discs and those next to them until the game ends
+    FogOfWar
+}
+
+impl Variant {
+    pub const ALL: [Variant; 8] = [
+        Variant::Standard, Variant::PopOut, Variant::Cylinder, Variant::PowerUp,
+        Variant::ScoreFour, Variant::GravityFlip, Variant::FreePlacement, Variant::FogOfWar
+    ];
+
+    // Columns and rows when no size is given: Score Four boards are 4x4 pegs 4 high, and
+    // Gravity Flip needs a square board
+    pub fn default_size(self) -> (usize, usize) {
+        let rules = match self {
+            Variant::ScoreFour => Rules::SCORE_FOUR,
+            Variant::GravityFlip => Rules::GRAVITY_FLIP,
+            _ => Rules::STANDARD
+        };
+        (rules.cols, rules.rows)
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Variant::Standard => "Standard",
+            Variant::PopOut => "PopOut",
+            Variant::Cylinder => "Cylinder",
+            Variant::PowerUp => "PowerUp",
+            Variant::ScoreFour => "ScoreFour",
+            Variant::GravityFlip => "GravityFlip",
+            Variant::FreePlacement => "FreePlacement",
+            Variant::FogOfWar => "FogOfWar"
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Variant {
+    type Err = RulesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Variant::ALL.into_iter()
+            .find(|v| v.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| RulesError::Variant(s.to_owned()))
+    }
+}
+
+// How the game is won, independent of the variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scoring {
+    #[default]
+    Normal,
+    // The first player to complete a line loses
+    Misere,
+    // Play goes on to the end, and whoever completed the most lines wins
+    CountFours
+}
+
+impl Scoring {
+    pub const ALL: [Scoring; 3] = [Scoring::Normal, Scoring::Misere, Scoring::CountFours];
+}
+
+impl fmt::Display for Scoring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scoring::Normal => "Normal",
+            Scoring::Misere => "Misere",
+            Scoring::CountFours => "CountFours"
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Scoring {
+    type Err = RulesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scoring::ALL.into_iter()
+            .find(|scoring| scoring.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| RulesError::Scoring(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    pub cols: usize,
+    pub rows: usize,
+    // Tiles in a row needed to win
+    pub connect: usize,
+    pub variant: Variant,
+    pub players: usize,
+    pub scoring: Scoring
+}
+
+impl Rules {
+    pub const STANDARD: Rules = Rules {
+        cols: 7, rows: 6, connect: 4, variant: Variant::Standard, players: 2, scoring: Scoring::Normal
+    };
+    // 4x4 pegs, 4 high
+    pub const SCORE_FOUR: Rules = Rules { cols: 16, rows: 4, variant: Variant::ScoreFour, ..Rules::STANDARD };
+    pub const GRAVITY_FLIP: Rules = Rules { cols: 7, rows: 7, variant: Variant::GravityFlip, ..Rules::STANDARD };
+
+    pub fn new(cols: usize, rows: usize, connect: usize) -> Result<Self, RulesError> {
+        if !(1..=MAX_SIZE).contains(&cols) || !(1..=MAX_SIZE).contains(&rows) {
+            return Err(RulesError::BoardSize(cols, rows));
+        }
+        if connect < 2 || connect > cols.max(rows) {
+            return Err(RulesError::Connect(connect));
+        }
+        Ok(Self { cols, rows, connect, ..Self::STANDARD })
+    }
+
+    // Rules with every part given, checked as by `new` and the `with_` methods
+    pub fn from_parts(cols: usize, rows: usize, connect: usize, variant: Variant, players: usize, scoring: Scoring) -> Result<Self, RulesError> {
+        Ok(Self::new(cols, rows, connect)?.with_variant(variant)?.with_players(players)?.with_scoring(scoring))
+    }
+
+    pub fn with_variant(self, variant: Variant) -> Result<Self, RulesError> {
+        let rules = Self { variant, ..self };
+        if let Some(side) = rules.peg_side() {
+            if side * side != rules.cols {
+                return Err(RulesError::Pegs(rules.cols));
+            }
+            if rules.connect > side.max(rules.rows) {
+                return Err(RulesError::Connect(rules.connect));
+            }
+        }
+        // Turning the board must not change its shape
+        if variant == Variant::GravityFlip && rules.cols != rules.rows {
+            return Err(RulesError::NotSquare(rules.cols, rules.rows));
+        }
+        Ok(rules)
+    }
+
+    pub fn with_players(self, players: usize) -> Result<Self, RulesError> {
+        if !(2..=MAX_PLAYERS).contains(&players) {
+            return Err(RulesError::Players(players));
+        }
+        Ok(Self { players, ..self })
+    }
+
+    pub fn with_scoring(self, scoring: Scoring) -> Self {
+        Self { scoring, ..self }
+    }
+
+    // Players in turn order
+    pub fn turn_order(&self) -> impl Iterator<Item = Tile> {
+        (0..self.players).map(Tile::new)
+    }
+
+    // Whether column 0 and the last column are adjacent
+    pub fn wraps(&self) -> bool {
+        self.variant == Variant::Cylinder
+    }
+
+    pub fn has_gravity(&self) -> bool {
+        self.variant != Variant::FreePlacement
+    }
+
+    // Side of the square of pegs in Score Four, where column `x + side * y` is the peg at (x, y)
+    pub fn peg_side(&self) -> Option<usize> {
+        (self.variant == Variant::ScoreFour).then(|| self.cols.isqrt())
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RulesError {
+    BoardSize(usize, usize),
+    Connect(usize),
+    Variant(String),
+    Players(usize),
+    Scoring(String),
+    // Score Four pegs must form a square
+    Pegs(usize),
+    NotSquare(usize, usize)
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::BoardSize(cols, rows) => write!(f, "a {cols}x{rows} board is not between 1x1 and {MAX_SIZE}x{MAX_SIZE}"),
+            RulesError::Connect(n) => write!(f, "cannot connect {n} on this board"),
+            RulesError::Variant(v) => write!(f, "unknown variant {v:?}"),
+            RulesError::Players(n) => write!(f, "{n} players is not between 2 and {MAX_PLAYERS}"),
+            RulesError::Scoring(s) => write!(f, "unknown scoring {s:?}"),
+            RulesError::Pegs(n) => write!(f, "{n} pegs do not form a square"),
+            RulesError::NotSquare(cols, rows) => write!(f, "a {cols}x{rows} board is not square")
+        }
+    }
+}
+
+impl Error for RulesError {}
+
+// A player, numbered from 0 in turn order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tile(u8);
+
+impl Tile {
+    pub const RED: Tile = Tile(0);
+    pub const YELLOW: Tile = Tile(1);
+    pub const GREEN: Tile = Tile(2);
+    pub const BLUE: Tile = Tile(3);
+
+    pub fn new(id: usize) -> Self {
+        assert!(id < MAX_PLAYERS, "player {id} out of range");
+        Tile(id as u8)
+    }
+
+    pub fn id(self) -> usize {
+        self.0 as usize
+    }
+
+    // The player whose turn follows this one's
+    pub fn next(self, players: usize) -> Self {
+        Tile::new((self.id() + 1) % players)
+    }
+
+    // The opponent in a two-player game
+    pub fn other(self) -> Self {
+        self.next(2)
+    }
+}
+
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Tile::RED => "Red",
+            Tile::YELLOW => "Yellow",
+            Tile::GREEN => "Green",
+            _ => "Blue"
+        };
+        f.write_str(name)
+    }
+}
+
+// One-off special discs of the Power Up variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Power {
+    // Clears the column below it
+    Anvil,
+    // Blows up an opponent disc next to where it lands
+    Bomb,
+    // Counts for nobody
+    Wall,
+    // Lets the player move again
+    Double
+}
+
+impl Power {
+    pub const ALL: [Power; 4] = [Power::Anvil, Power::Bomb, Power::Wall, Power::Double];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for Power {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Power::Anvil => "anvil",
+            Power::Bomb => "bomb",
+            Power::Wall => "wall",
+            Power::Double => "double"
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Disc {
+    pub owner: Tile,
+    pub power: Option<Power>
+}
+
+impl Disc {
+    pub fn new(owner: Tile) -> Self {
+        Self { owner, power: None }
+    }
+
+    // The player this disc counts for in a line
+    pub fn counts_for(self) -> Option<Tile> {
+        (self.power != Some(Power::Wall)).then_some(self.owner)
+    }
+}
+
+// Row 0 is the top of the board
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Grid {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Option<Disc>>
+}
+
+impl Grid {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self { cols, rows, cells: vec![None; cols * rows] }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn disc(&self, row: usize, col: usize) -> Option<Disc> {
+        self.cells[row * self.cols + col]
+    }
+
+    pub fn set_disc(&mut self, row: usize, col: usize, disc: Option<Disc>) {
+        self.cells[row * self.cols + col] = disc;
+    }
+
+    // The player a cell counts for, which is nobody for empty cells and walls
+    pub fn get(&self, row: usize, col: usize) -> Option<Tile> {
+        self.disc(row, col).and_then(Disc::counts_for)
+    }
+
+    // Places an ordinary disc
+    pub fn set(&mut self, row: usize, col: usize, tile: Option<Tile>) {
+        self.set_disc(row, col, tile.map(Disc::new));
+    }
+
+    pub fn is_occupied(&self, row: usize, col: usize) -> bool {
+        self.disc(row, col).is_some()
+    }
+
+    // Occupied cells as (row, col, disc)
+    pub fn discs(&self) -> impl Iterator<Item = (usize, usize, Disc)> + '_ {
+        self.cells.iter()
+            .enumerate()
+            .filter_map(|(i, disc)| Some((i / self.cols, i % self.cols, (*disc)?)))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.cells.iter().all(Option::is_some)
+    }
+
+    // Lowest empty row of a column
+    pub fn drop_row(&self, col: usize) -> Option<usize> {
+        (0..self.rows).rev().find(|&row| !self.is_occupied(row, col))
+    }
+
+    // Highest occupied row of a column
+    pub fn top_row(&self, col: usize) -> Option<usize> {
+        (0..self.rows).find(|&row| self.is_occupied(row, col))
+    }
+
+    // The grid turned a quarter turn, before anything falls
+    pub fn rotated(&self, rotation: Rotation) -> Grid {
+        let mut grid = Grid::new(self.rows, self.cols);
+        for (row, col, disc) in self.discs() {
+            let (row, col) = match rotation {
+                Rotation::Clockwise => (col, self.rows - 1 - row),
+                Rotation::Anticlockwise => (self.cols - 1 - col, row)
+            };
+            grid.set_disc(row, col, Some(disc));
+        }
+        grid
+    }
+
+    // Lets every disc fall to the bottom of its column
+    pub fn settle(&mut self) {
+        for col in 0..self.cols {
+            let discs: Vec<_> = (0..self.rows).rev().filter_map(|row| self.disc(row, col)).collect();
+            for row in 0..self.rows {
+                self.set_disc(row, col, None);
+            }
+            for (height, disc) in discs.into_iter().enumerate() {
+                self.set_disc(self.rows - 1 - height, col, Some(disc));
+            }
+        }
+    }
+
+    // Takes a disc out, letting everything above it fall one row
+    pub fn remove(&mut self, row: usize, col: usize) {
+        for r in (1..=row).rev() {
+            self.set_disc(r, col, self.disc(r - 1, col));
+        }
+        self.set_disc(0, col, None);
+    }
+}
+
+// (row, col) of each tile in a winning line
+pub type Line = Vec<(usize, usize)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameOutcome {
+    InProgress,
+    // With every cell of the winner's lines when counting fours
+    Win(Tile, Line),
+    // Completing a line in misère
+    Loss(Tile, Line),
+    Draw
+}
+
+// Neighbouring cells, for aiming bombs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest
+}
+
+impl Direction {
+    pub const ALL: [Direction; 8] = [
+        Direction::North, Direction::NorthEast, Direction::East, Direction::SouthEast,
+        Direction::South, Direction::SouthWest, Direction::West, Direction::NorthWest
+    ];
+
+    // (row, col) offset, with rows counting down from the top
+    pub fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::North => (-1, 0),
+            Direction::NorthEast => (-1, 1),
+            Direction::East => (0, 1),
+            Direction::SouthEast => (1, 1),
+            Direction::South => (1, 0),
+            Direction::SouthWest => (1, -1),
+            Direction::West => (0, -1),
+            Direction::NorthWest => (-1, -1)
+        }
+    }
+
+    fn abbreviation(self) -> &'static str {
+        match self {
+            Direction::North => "n",
+            Direction::NorthEast => "ne",
+            Direction::East => "e",
+            Direction::SouthEast => "se",
+            Direction::South => "s",
+            Direction::SouthWest => "sw",
+            Direction::West => "w",
+            Direction::NorthWest => "nw"
+        }
+    }
+}
+
+// As seen from the front of the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    Clockwise,
+    Anticlockwise
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Move {
+    Drop(usize),
+    // Remove the current player's tile from the bottom of a column
+    Pop(usize),
+    // Drop a power disc other than a bomb
+    Power(Power, usize),
+    // Drop a bomb, blowing up the opponent disc in the given direction from where it lands
+    Bomb(usize, Direction),
+    // Turn the board so that every disc falls towards the new bottom
+    Rotate(Rotation),
+    // Put a disc in the empty cell at (row, col) when there is no gravity
+    Place(usize, usize)
+}
+
+impl Move {
+    pub fn col(self) -> Option<usize> {
+        match self {
+            Move::Drop(col) | Move::Pop(col) | Move::Power(_, col) | Move::Bomb(col, _) | Move::Place(_, col) => Some(col),
+            Move::Rotate(_) => None
+        }
+    }
+
+    pub fn power(self) -> Option<Power> {
+        match self {
+            Move::Power(power, _) => Some(power),
+            Move::Bomb(..) => Some(Power::Bomb),
+            _ => None
+        }
+    }
+}
+
+// Columns are numbered from 1 for people. Pops are prefixed with "p", power discs with
+// their initial, and bombs are followed by the direction of their target, as in "b4sw".
+// Rotations are "cw" and "ccw", and free placements give the column then the row counted
+// from the top, as in "4:2"
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Move::Drop(col) => write!(f, "{}", col + 1),
+            Move::Pop(col) => write!(f, "p{}", col + 1),
+            Move::Power(power, col) => write!(f, "{}{}", &power.to_string()[..1], col + 1),
+            Move::Bomb(col, dir) => write!(f, "b{}{}", col + 1, dir.abbreviation()),
+            Move::Rotate(Rotation::Clockwise) => write!(f, "cw"),
+            Move::Rotate(Rotation::Anticlockwise) => write!(f, "ccw"),
+            Move::Place(row, col) => write!(f, "{}:{}", col + 1, row + 1)
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        match s.as_str() {
+            "cw" => return Ok(Move::Rotate(Rotation::Clockwise)),
+            "ccw" => return Ok(Move::Rotate(Rotation::Anticlockwise)),
+            _ => {}
+        }
+        if let Some((col, row)) = s.split_once(':') {
+            return match (col.parse::<usize>(), row.parse::<usize>()) {
+                (Ok(col @ 1..), Ok(row @ 1..)) => Ok(Move::Place(row - 1, col - 1)),
+                _ => Err(())
+            };
+        }
+        let (prefix, rest) = s.split_at(s.find(|c: char| c.is_ascii_digit()).ok_or(())?);
+        let (col, suffix) = rest.split_at(rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()));
+        let col = match col.parse::<usize>() {
+            Ok(col @ 1..) => col - 1,
+            _ => return Err(())
+        };
+
+        match (prefix, suffix) {
+            ("", "") => Ok(Move::Drop(col)),
+            ("p", "") => Ok(Move::Pop(col)),
+            ("a", "") => Ok(Move::Power(Power::Anvil, col)),
+            ("w", "") => Ok(Move::Power(Power::Wall, col)),
+            ("d", "") => Ok(Move::Power(Power::Double, col)),
+            ("b", dir) => Direction::ALL.into_iter()
+                .find(|d| d.abbreviation() == dir)
+                .map(|d| Move::Bomb(col, d))
+                .ok_or(()),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    GameOver,
+    OutOfBounds(usize),
+    ColumnFull(usize),
+    // The bottom tile of the column is not the current player's
+    CannotPop(usize),
+    // The move is not allowed in this variant
+    Variant(Variant),
+    // The current player has already used this power disc
+    PowerUsed(Power),
+    // Bombs must be aimed at an opponent disc next to where they land
+    BadTarget(usize, Direction),
+    // Bombs are only played as `Move::Bomb`, with a target
+    NoTarget(usize),
+    RowOutOfBounds(usize),
+    Occupied(usize, usize)
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::GameOver => write!(f, "the game is already over"),
+            // Columns are numbered from 1 for people
+            MoveError::OutOfBounds(col) => write!(f, "column {} is out of bounds", col + 1),
+            MoveError::ColumnFull(col) => write!(f, "column {} is full", col + 1),
+            MoveError::CannotPop(col) => write!(f, "cannot pop column {}", col + 1),
+            MoveError::Variant(v) => write!(f, "move not allowed in {v}"),
+            MoveError::PowerUsed(power) => write!(f, "the {power} disc has already been used"),
+            MoveError::BadTarget(col, dir) => write!(f, "no opponent disc {} of a bomb in column {}", dir.abbreviation(), col + 1),
+            MoveError::NoTarget(col) => write!(f, "a bomb in column {} needs a target", col + 1),
+            MoveError::RowOutOfBounds(row) => write!(f, "row {} is out of bounds", row + 1),
+            MoveError::Occupied(row, col) => write!(f, "row {}, column {} is already taken", row + 1, col + 1)
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+// Why a position cannot be played on from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupError {
+    // The grid does not match the rules' board
+    BoardSize(usize, usize),
+    // A disc of a player who is not in the game
+    Player(Tile),
+    // A disc at (row, col) with an empty cell below it
+    Floating(usize, usize),
+    // Discs of each player in turn order, which cannot occur with this player to move
+    DiscCount(Vec<usize>, Tile),
+    // More than one player already has a line
+    SeveralLines
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::BoardSize(cols, rows) => write!(f, "a {cols}x{rows} grid does not fit the board"),
+            SetupError::Player(tile) => write!(f, "{tile} is not playing"),
+            SetupError::Floating(_, col) => write!(f, "a disc in column {} has an empty cell below it", col + 1),
+            SetupError::DiscCount(counts, to_move) => {
+                let counts: Vec<_> = counts.iter().map(usize::to_string).collect();
+                write!(f, "{} discs cannot occur with {to_move} to move", counts.join("-"))
+            },
+            SetupError::SeveralLines => write!(f, "more than one player has a line")
+        }
+    }
+}
+
+impl Error for SetupError {}
+
+// State restored by undoing a move
+#[derive(Debug, Clone)]
+struct Snapshot {
+    grid: Grid,
+    current_player: Tile,
+    spent: [u8; MAX_PLAYERS]
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    rules: Rules,
+    grid: Grid,
+    current_player: Tile,
+    outcome: GameOutcome,
+    // Power discs used by each player, one bit per power
+    spent: [u8; MAX_PLAYERS],
+    // Moves played so far with the state before each, and undone moves that can be redone
+    history: Vec<Move>,
+    snapshots: Vec<Snapshot>,
+    redo: Vec<Move>,
+    // Hash of every position so far including the current one, for repetition draws
+    positions: Vec<u64>
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(Rules::STANDARD)
+    }
+}
+
+impl Game {
+    pub fn new(rules: Rules) -> Self {
+        let mut game = Self {
+            rules,
+            grid: Grid::new(rules.cols, rules.rows),
+            current_player: Tile::RED,
+            outcome: GameOutcome::InProgress,
+            spent: [0; MAX_PLAYERS],
+            history: Vec::new(),
+            snapshots: Vec::new(),
+            redo: Vec::new(),
+            positions: Vec::new()
+        };
+        game.positions.push(game.position_hash());
+        game
+    }
+
+    // Plays on from a position set up by hand rather than from an empty board
+    pub fn from_position(rules: Rules, grid: Grid, to_move: Tile) -> Result<Self, SetupError> {
+        let (cols, rows) = (grid.cols(), grid.rows());
+        if (cols, rows) != (rules.cols, rules.rows) {
+            return Err(SetupError::BoardSize(cols, rows));
+        }
+        if to_move.id() >= rules.players {
+            return Err(SetupError::Player(to_move));
+        }
+
+        let mut counts = vec![0; rules.players];
+        for (row, col, disc) in grid.discs() {
+            if disc.owner.id() >= rules.players {
+                return Err(SetupError::Player(disc.owner));
+            }
+            if rules.has_gravity() && row + 1 < rows && !grid.is_occupied(row + 1, col) {
+                return Err(SetupError::Floating(row, col));
+            }
+            counts[disc.owner.id()] += 1;
+        }
+
+        // Everyone before the player to move has had one more turn, unless discs can be
+        // taken off the board or a turn can add none or two
+        if !matches!(rules.variant, Variant::PopOut | Variant::PowerUp | Variant::GravityFlip) {
+            let turns = counts[to_move.id()];
+            if counts.iter().enumerate().any(|(id, &count)| count != turns + usize::from(id < to_move.id())) {
+                return Err(SetupError::DiscCount(counts, to_move));
+            }
+        }
+
+        let mut game = Self::new(rules);
+        game.grid = grid;
+        game.current_player = to_move;
+        game.positions = vec![game.position_hash()];
+        if rules.scoring != Scoring::CountFours {
+            let mut owners: Vec<_> = game.lines().map(|(tile, _)| tile).collect();
+            owners.dedup();
+            if owners.iter().any(|&tile| tile != owners[0]) {
+                return Err(SetupError::SeveralLines);
+            }
+        }
+        let mover = Tile::new((to_move.id() + rules.players - 1) % rules.players);
+        game.outcome = game.compute_outcome(mover);
+        Ok(game)
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn current_player(&self) -> Tile {
+        self.current_player
+    }
+
+    pub fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    // The position the moves in the history were played from, with the player to move and
+    // the power discs used by then
+    pub fn start(&self) -> (&Grid, Tile, Vec<(Tile, Power)>) {
+        match self.snapshots.first() {
+            Some(snapshot) => (&snapshot.grid, snapshot.current_player, self.powers_spent_in(&snapshot.spent)),
+            None => (&self.grid, self.current_player, self.spent_powers())
+        }
+    }
+
+    pub fn last_move(&self) -> Option<Move> {
+        self.history.last().copied()
+    }
+
+    // The player who made the last move
+    pub fn last_mover(&self) -> Option<Tile> {
+        self.snapshots.last().map(|snapshot| snapshot.current_player)
+    }
+
+    pub fn outcome(&self) -> &GameOutcome {
+        &self.outcome
+    }
+
+    pub fn win(&self) -> Option<Tile> {
+        match self.outcome {
+            GameOutcome::Win(tile, _) => Some(tile),
+            _ => None
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.outcome != GameOutcome::InProgress
+    }
+
+    // Whether a player still has a power disc to use
+    pub fn has_power(&self, tile: Tile, power: Power) -> bool {
+        self.rules.variant == Variant::PowerUp && self.spent[tile.id()] & power.bit() == 0
+    }
+
+    // Power discs used so far by each player
+    pub fn spent_powers(&self) -> Vec<(Tile, Power)> {
+        self.powers_spent_in(&self.spent)
+    }
+
+    fn powers_spent_in(&self, spent: &[u8; MAX_PLAYERS]) -> Vec<(Tile, Power)> {
+        self.rules.turn_order()
+            .flat_map(|tile| Power::ALL.into_iter().map(move |power| (tile, power)))
+            .filter(|&(tile, power)| spent[tile.id()] & power.bit() != 0)
+            .collect()
+    }
+
+    // The same position with power discs already used, for positions set up by hand
+    pub fn with_spent_powers(mut self, spent: &[(Tile, Power)]) -> Self {
+        for &(tile, power) in spent {
+            self.spent[tile.id()] |= power.bit();
+        }
+        self
+    }
+
+    // Whether a player can see which disc is in a cell, which in fog of war is only true of
+    // their own discs and those next to them until the game is over
+    pub fn is_visible(&self, row: usize, col: usize, viewer: Tile) -> bool {
+        if self.rules.variant != Variant::FogOfWar || self.is_over() {
+            return true;
+        }
+        let Some(disc) = self.grid.disc(row, col) else {
+            return true;
+        };
+        disc.owner == viewer || Direction::ALL.into_iter().any(|dir| {
+            let (dr, dc) = dir.offset();
+            match (row.checked_add_signed(dr), col.checked_add_signed(dc)) {
+                (Some(r), Some(c)) if r < self.rules.rows && c < self.rules.cols => {
+                    self.grid.disc(r, c).is_some_and(|disc| disc.owner == viewer)
+                },
+                _ => false
+            }
+        })
+    }
+
+    pub fn check_move(&self, mv: Move) -> Result<(), MoveError> {
+        if self.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let variant = self.rules.variant;
+        // Only rotations have no column, and they can always be made
+        let Some(col) = mv.col() else {
+            return if variant == Variant::GravityFlip { Ok(()) } else { Err(MoveError::Variant(variant)) };
+        };
+        if col >= self.rules.cols {
+            return Err(MoveError::OutOfBounds(col));
+        }
+        match mv {
+            // Nothing else can be played without gravity
+            Move::Place(..) if variant != Variant::FreePlacement => return Err(MoveError::Variant(variant)),
+            Move::Place(row, _) if row >= self.rules.rows => return Err(MoveError::RowOutOfBounds(row)),
+            Move::Place(row, _) if self.grid.is_occupied(row, col) => return Err(MoveError::Occupied(row, col)),
+            Move::Place(..) => return Ok(()),
+            _ if variant == Variant::FreePlacement => return Err(MoveError::Variant(variant)),
+            Move::Pop(_) if variant != Variant::PopOut => return Err(MoveError::Variant(variant)),
+            Move::Pop(_) if self.grid.disc(self.rules.rows - 1, col) != Some(Disc::new(self.current_player)) => {
+                return Err(MoveError::CannotPop(col));
+            },
+            Move::Pop(_) => return Ok(()),
+            Move::Power(..) | Move::Bomb(..) if variant != Variant::PowerUp => return Err(MoveError::Variant(variant)),
+            Move::Power(Power::Bomb, _) => return Err(MoveError::NoTarget(col)),
+            _ => {}
+        }
+
+        if self.grid.drop_row(col).is_none() {
+            return Err(MoveError::ColumnFull(col));
+        }
+        if let Some(power) = mv.power().filter(|&power| !self.has_power(self.current_player, power)) {
+            return Err(MoveError::PowerUsed(power));
+        }
+        if let Move::Bomb(_, dir) = mv {
+            if self.bomb_target(col, dir).is_none() {
+                return Err(MoveError::BadTarget(col, dir));
+            }
+        }
+        Ok(())
+    }
+
+    // The opponent disc a bomb dropped in a column would hit
+    pub fn bomb_target(&self, col: usize, dir: Direction) -> Option<(usize, usize)> {
+        let row = self.grid.drop_row(col)?;
+        let (dr, dc) = dir.offset();
+        let (row, col) = (row.checked_add_signed(dr)?, col.checked_add_signed(dc)?);
+        if row >= self.rules.rows || col >= self.rules.cols {
+            return None;
+        }
+        let disc = self.grid.disc(row, col)?;
+        (disc.owner != self.current_player).then_some((row, col))
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        self.check_move(mv).is_ok()
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = vec![Move::Rotate(Rotation::Clockwise), Move::Rotate(Rotation::Anticlockwise)];
+        for col in 0..self.rules.cols {
+            moves.extend((0..self.rules.rows).map(|row| Move::Place(row, col)));
+            moves.extend([Move::Drop(col), Move::Pop(col)]);
+            moves.extend([Power::Anvil, Power::Wall, Power::Double].map(|power| Move::Power(power, col)));
+            moves.extend(Direction::ALL.map(|dir| Move::Bomb(col, dir)));
+        }
+        moves.retain(|&mv| self.is_legal(mv));
+        moves
+    }
+
+    // Drops a tile for the current player, returning the row it landed in
+    pub fn play(&mut self, col: usize) -> Result<usize, MoveError> {
+        self.play_move(Move::Drop(col))?;
+        Ok(self.grid.top_row(col).unwrap())
+    }
+
+    pub fn play_move(&mut self, mv: Move) -> Result<(), MoveError> {
+        self.check_move(mv)?;
+        self.redo.clear();
+        self.apply(mv);
+        Ok(())
+    }
+
+    fn apply(&mut self, mv: Move) {
+        let mover = self.current_player;
+        self.snapshots.push(Snapshot { grid: self.grid.clone(), current_player: mover, spent: self.spent });
+
+        let disc = Disc { owner: mover, power: mv.power() };
+        match mv {
+            // Everything above the bottom tile falls down one row
+            Move::Pop(col) => self.grid.remove(self.rules.rows - 1, col),
+            Move::Bomb(col, dir) => {
+                let (target_row, target_col) = self.bomb_target(col, dir).unwrap();
+                let row = self.grid.drop_row(col).unwrap();
+                self.grid.set_disc(row, col, Some(disc));
+                self.grid.remove(target_row, target_col);
+            },
+            Move::Rotate(rotation) => {
+                self.grid = self.grid.rotated(rotation);
+                self.grid.settle();
+            },
+            Move::Place(row, col) => self.grid.set_disc(row, col, Some(disc)),
+            Move::Drop(col) | Move::Power(_, col) => {
+                if mv.power() == Some(Power::Anvil) {
+                    for row in 0..self.rules.rows {
+                        self.grid.set_disc(row, col, None);
+                    }
+                }
+                let row = self.grid.drop_row(col).unwrap();
+                self.grid.set_disc(row, col, Some(disc));
+            }
+        }
+        if let Some(power) = mv.power() {
+            self.spent[mover.id()] |= power.bit();
+        }
+
+        if mv.power() != Some(Power::Double) {
+            self.current_player = mover.next(self.rules.players);
+        }
+        self.history.push(mv);
+        self.positions.push(self.position_hash());
+        self.outcome = self.compute_outcome(mover);
+    }
+
+    // Takes back the last move
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv = self.history.pop()?;
+        let snapshot = self.snapshots.pop().unwrap();
+        self.positions.pop();
+        self.grid = snapshot.grid;
+        self.current_player = snapshot.current_player;
+        self.spent = snapshot.spent;
+        // The game cannot have been over before its last move
+        self.outcome = GameOutcome::InProgress;
+        self.redo.push(mv);
+        Some(mv)
+    }
+
+    // Replays the last undone move
+    pub fn redo(&mut self) -> Option<Move> {
+        let mv = self.redo.pop()?;
+        self.apply(mv);
+        Some(mv)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    fn position_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.grid.hash(&mut hasher);
+        self.current_player.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn compute_outcome(&self, mover: Tile) -> GameOutcome {
+        let lines: Vec<_> = self.lines().collect();
+        let scoring = self.rules.scoring;
+
+        // Pops, anvils, bombs and rotations can complete lines for several players at once,
+        // in which case the line of the player who moved counts, then that of whoever comes
+        // soonest after them
+        let players = self.rules.players;
+        if scoring != Scoring::CountFours {
+            for tile in iter::successors(Some(mover), |tile| Some(tile.next(players))).take(players) {
+                if let Some((_, line)) = lines.iter().find(|(owner, _)| *owner == tile) {
+                    return match scoring {
+                        Scoring::Misere => GameOutcome::Loss(tile, line.clone()),
+                        _ => GameOutcome::Win(tile, line.clone())
+                    };
+                }
+            }
+        }
+
+        let over = match self.rules.variant {
+            Variant::PopOut if self.legal_moves_ignoring_outcome() == 0 => true,
+            Variant::PopOut | Variant::GravityFlip if self.repetitions() >= REPETITION_DRAW => true,
+            Variant::PopOut => false,
+            _ => self.grid.is_full()
+        };
+        if !over {
+            return GameOutcome::InProgress;
+        }
+        if scoring != Scoring::CountFours {
+            return GameOutcome::Draw;
+        }
+
+        // The outright highest score wins
+        let scores = self.scores();
+        let best = scores.iter().copied().max().unwrap();
+        match scores.iter().position(|&score| score == best) {
+            Some(id) if scores.iter().filter(|&&score| score == best).count() == 1 => {
+                let winner = Tile::new(id);
+                let mut cells: Line = lines.into_iter().filter(|(owner, _)| *owner == winner).flat_map(|(_, line)| line).collect();
+                cells.sort();
+                cells.dedup();
+                GameOutcome::Win(winner, cells)
+            },
+            _ => GameOutcome::Draw
+        }
+    }
+
+    // Every completed line, with the player it counts for
+    pub fn lines(&self) -> impl Iterator<Item = (Tile, Line)> + '_ {
+        self.all_lines().into_iter().filter_map(|line| {
+            let (row, col) = line[0];
+            let tile = self.grid.get(row, col)?;
+            line.iter().all(|&(r, c)| self.grid.get(r, c) == Some(tile)).then_some((tile, line))
+        })
+    }
+
+    // Whether a disc of the player's in the empty cell would complete a line through it,
+    // without playing it
+    pub fn completes_line(&self, row: usize, col: usize, tile: Tile) -> bool {
+        if self.rules.peg_side().is_some() {
+            return self.all_lines().into_iter().any(|line| {
+                line.contains(&(row, col)) && line.iter().all(|&(r, c)| (r, c) == (row, col) || self.grid.get(r, c) == Some(tile))
+            });
+        }
+        let Rules { cols, rows, connect, .. } = self.rules;
+        let wraps = self.rules.wraps();
+        // The player's discs running from the cell one way, up to the empty cell itself
+        // when wrapping round
+        let run = |dr: isize, dc: isize| {
+            (1..).map_while(|k: isize| {
+                let r = row.checked_add_signed(dr * k).filter(|&r| r < rows)?;
+                let c = col as isize + dc * k;
+                let c = if wraps { c.rem_euclid(cols as isize) as usize } else { usize::try_from(c).ok().filter(|&c| c < cols)? };
+                (self.grid.get(r, c) == Some(tile)).then_some(())
+            }).count()
+        };
+        [(0, 1), (1, 0), (1, 1), (-1, 1)].into_iter().any(|(dr, dc)| {
+            let len = 1 + run(dr, dc) + run(-dr, -dc);
+            // A wrapped line may not reuse a column
+            let len = if wraps && dc != 0 { len.min(cols) } else { len };
+            len >= connect
+        })
+    }
+
+    // Number of completed lines of each player, in turn order
+    pub fn scores(&self) -> Vec<usize> {
+        let mut scores = vec![0; self.rules.players];
+        for (tile, _) in self.lines() {
+            scores[tile.id()] += 1;
+        }
+        scores
+    }
+
+    // Number of times the current position has occurred
+    pub fn repetitions(&self) -> usize {
+        let current = self.positions.last().unwrap();
+        self.positions.iter().filter(|&hash| hash == current).count()
+    }
+
+    fn legal_moves_ignoring_outcome(&self) -> usize {
+        let bottom = self.rules.rows - 1;
+        (0..self.rules.cols)
+            .filter(|&col| !self.grid.is_occupied(0, col) || self.grid.disc(bottom, col) == Some(Disc::new(self.current_player)))
+            .count()
+    }
+
+    // Every place a line could be made, whatever is in it
+    fn all_lines(&self) -> Vec<Line> {
+        if let Some(side) = self.rules.peg_side() {
+            return self.all_lines_3d(side);
+        }
+        let Rules { cols, rows, connect, .. } = self.rules;
+        let n = connect as isize;
+        let wraps = self.rules.wraps();
+        let mut lines = Vec::new();
+
+        // Horizontal, vertical and both diagonals, from each possible start
+        for (dr, dc) in [(0, 1), (1, 0), (1, 1), (-1, 1)] {
+            // A wrapped line may not reuse a column
+            if wraps && dc != 0 && connect > cols {
+                continue;
+            }
+            for row in 0..rows as isize {
+                for col in 0..cols as isize {
+                    let (end_row, end_col) = (row + dr * (n - 1), col + dc * (n - 1));
+                    if end_row < 0 || end_row >= rows as isize || (!wraps && end_col >= cols as isize) {
+                        continue;
+                    }
+                    // A row wrapped all the way round is the same line from any start
+                    if wraps && dr == 0 && connect == cols && col > 0 {
+                        continue;
+                    }
+
+                    let cell = |k: isize| ((row + dr * k) as usize, (col + dc * k).rem_euclid(cols as isize) as usize);
+                    lines.push((0..n).map(cell).collect());
+                }
+            }
+        }
+
+        lines
+    }
+
+    fn all_lines_3d(&self, side: usize) -> Vec<Line> {
+        let n = self.rules.connect as isize;
+        let (side, rows) = (side as isize, self.rules.rows as isize);
+        let in_bounds = |x, y, z| (0..side).contains(&x) && (0..side).contains(&y) && (0..rows).contains(&z);
+        let mut lines = Vec::new();
+
+        // The 13 directions, one from each pair of opposites
+        let directions = (-1..=1)
+            .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (dx, dy, dz))))
+            .filter(|&dir| dir > (0, 0, 0));
+        for (dx, dy, dz) in directions {
+            for x in 0..side {
+                for y in 0..side {
+                    for z in 0..rows {
+                        if !in_bounds(x + dx * (n - 1), y + dy * (n - 1), z + dz * (n - 1)) {
+                            continue;
+                        }
+
+                        // z counts up from the bottom of the peg
+                        let cell = |k: isize| ((rows - 1 - (z + dz * k)) as usize, (x + dx * k + side * (y + dy * k)) as usize);
+                        lines.push((0..n).map(cell).collect());
+                    }
+                }
+            }
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play_all(game: &mut Game, cols: &[usize]) {
+        for &col in cols {
+            game.play(col).unwrap();
+        }
+    }
+
+    #[test]
+    fn play_stacks_tiles_from_the_bottom() {
+        let mut game = Game::default();
+        assert_eq!(game.play(3), Ok(5));
+        assert_eq!(game.play(3), Ok(4));
+        assert_eq!(game.grid().get(5, 3), Some(Tile::RED));
+        assert_eq!(game.grid().get(4, 3), Some(Tile::YELLOW));
+        assert_eq!(game.current_player(), Tile::RED);
+    }
+
+    #[test]
+    fn check_move_errors() {
+        let mut game = Game::default();
+        assert_eq!(game.play(7), Err(MoveError::OutOfBounds(7)));
+        play_all(&mut game, &[0; 6]);
+        assert_eq!(game.play(0), Err(MoveError::ColumnFull(0)));
+        assert_eq!(game.check_move(Move::Pop(1)), Err(MoveError::Variant(Variant::Standard)));
+        assert_eq!(game.check_move(Move::Rotate(Rotation::Clockwise)), Err(MoveError::Variant(Variant::Standard)));
+        // A failed move changes nothing
+        assert_eq!(game.history().len(), 6);
+        assert_eq!(game.current_player(), Tile::RED);
+    }
+
+    #[test]
+    fn horizontal_win() {
+        let mut game = Game::default();
+        play_all(&mut game, &[0, 0, 1, 1, 2, 2, 3]);
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(5, 0), (5, 1), (5, 2), (5, 3)]));
+        assert_eq!(game.play(4), Err(MoveError::GameOver));
+    }
+
+    #[test]
+    fn vertical_and_diagonal_wins() {
+        let mut game = Game::default();
+        play_all(&mut game, &[0, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(game.win(), Some(Tile::RED));
+
+        let mut game = Game::default();
+        play_all(&mut game, &[0, 1, 1, 2, 2, 3, 2, 3, 3, 6, 3]);
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(5, 0), (4, 1), (3, 2), (2, 3)]));
+    }
+
+    #[test]
+    fn full_board_is_a_draw() {
+        let mut game = Game::default();
+        play_all(&mut game, &[
+            3, 2, 6, 2, 2, 3, 3, 4, 0, 4, 2, 6, 6, 3, 3, 3, 4, 2, 6, 4, 5,
+            6, 2, 4, 1, 4, 6, 0, 1, 1, 1, 0, 5, 0, 1, 5, 0, 1, 5, 0, 5, 5
+        ]);
+        assert!(game.grid().is_full());
+        assert_eq!(*game.outcome(), GameOutcome::Draw);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_outcome() {
+        let mut game = Game::default();
+        play_all(&mut game, &[0, 0, 1, 1, 2, 2, 3]);
+        let won = game.outcome().clone();
+
+        assert_eq!(game.undo(), Some(Move::Drop(3)));
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        assert_eq!(game.current_player(), Tile::RED);
+        assert!(!game.grid().is_occupied(5, 3));
+
+        assert_eq!(game.redo(), Some(Move::Drop(3)));
+        assert_eq!(*game.outcome(), won);
+        assert!(!game.can_redo());
+
+        // Playing a new move drops the moves that could have been redone
+        game.undo();
+        game.undo();
+        game.play(6).unwrap();
+        assert!(!game.can_redo());
+    }
+
+    fn rules(variant: Variant) -> Rules {
+        Rules::STANDARD.with_variant(variant).unwrap()
+    }
+
+    #[test]
+    fn pop_out() {
+        let mut game = Game::new(rules(Variant::PopOut));
+        play_all(&mut game, &[3, 3, 4]);
+        assert_eq!(game.check_move(Move::Pop(3)), Err(MoveError::CannotPop(3)));
+        assert_eq!(game.check_move(Move::Pop(0)), Err(MoveError::CannotPop(0)));
+        play_all(&mut game, &[0]);
+        game.play_move(Move::Pop(3)).unwrap();
+        // The yellow disc fell into the bottom row
+        assert_eq!(game.grid().get(5, 3), Some(Tile::YELLOW));
+        assert!(!game.grid().is_occupied(4, 3));
+        game.play_move(Move::Pop(0)).unwrap();
+        assert!(!game.grid().is_occupied(5, 0));
+        assert_eq!(game.current_player(), Tile::RED);
+    }
+
+    #[test]
+    fn pop_completing_only_an_opponent_line() {
+        let mut grid = Grid::new(7, 6);
+        grid.set(5, 0, Some(Tile::RED));
+        grid.set(4, 0, Some(Tile::YELLOW));
+        for col in 1..4 {
+            grid.set(5, col, Some(Tile::YELLOW));
+        }
+        let mut game = Game::from_position(rules(Variant::PopOut), grid, Tile::RED).unwrap();
+        game.play_move(Move::Pop(0)).unwrap();
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::YELLOW, vec![(5, 0), (5, 1), (5, 2), (5, 3)]));
+    }
+
+    #[test]
+    fn pop_out_threefold_repetition() {
+        let mut game = Game::new(rules(Variant::PopOut));
+        for _ in 0..2 {
+            assert_eq!(*game.outcome(), GameOutcome::InProgress);
+            play_all(&mut game, &[0, 1]);
+            game.play_move(Move::Pop(0)).unwrap();
+            game.play_move(Move::Pop(1)).unwrap();
+        }
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(*game.outcome(), GameOutcome::Draw);
+        // Taking a move back undoes the draw
+        game.undo();
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+    }
+
+    #[test]
+    fn pop_out_full_board_goes_on() {
+        let mut game = Game::new(rules(Variant::PopOut));
+        play_all(&mut game, &[
+            3, 2, 6, 2, 2, 3, 3, 4, 0, 4, 2, 6, 6, 3, 3, 3, 4, 2, 6, 4, 5,
+            6, 2, 4, 1, 4, 6, 0, 1, 1, 1, 0, 5, 0, 1, 5, 0, 1, 5, 0, 5, 5
+        ]);
+        assert!(game.grid().is_full());
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        assert!(game.legal_moves().iter().all(|mv| matches!(mv, Move::Pop(_))));
+    }
+
+    #[test]
+    fn cylinder_lines_wrap_around() {
+        let moves = [5, 3, 6, 3, 0, 3, 1];
+        let mut game = Game::new(rules(Variant::Cylinder));
+        play_all(&mut game, &moves);
+        let GameOutcome::Win(Tile::RED, mut line) = game.outcome().clone() else {
+            panic!("no win: {:?}", game.outcome());
+        };
+        line.sort();
+        assert_eq!(line, vec![(5, 0), (5, 1), (5, 5), (5, 6)]);
+
+        let mut game = Game::default();
+        play_all(&mut game, &moves);
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+    }
+
+    #[test]
+    fn cylinder_diagonal_wraps_around() {
+        let mut game = Game::new(rules(Variant::Cylinder));
+        // Red builds the diagonal (5, 5) (4, 6) (3, 0) (2, 1)
+        play_all(&mut game, &[5, 6, 6, 0, 1, 0, 0, 1, 2, 1, 1]);
+        assert_eq!(game.win(), Some(Tile::RED));
+        // Lines are no longer than the board is wide
+        let mut game = Game::new(Rules::new(4, 6, 4).unwrap().with_variant(Variant::Cylinder).unwrap());
+        play_all(&mut game, &[0, 0, 1, 1, 2, 2, 3]);
+        assert_eq!(game.scores(), vec![1, 0]);
+    }
+
+    #[test]
+    fn power_moves_need_power_up() {
+        let game = Game::default();
+        assert_eq!(game.check_move(Move::Power(Power::Anvil, 0)), Err(MoveError::Variant(Variant::Standard)));
+        assert_eq!(game.check_move(Move::Bomb(0, Direction::South)), Err(MoveError::Variant(Variant::Standard)));
+    }
+
+    #[test]
+    fn anvil_clears_its_column() {
+        let mut game = Game::new(rules(Variant::PowerUp));
+        play_all(&mut game, &[2, 2, 2, 2]);
+        game.play_move(Move::Power(Power::Anvil, 2)).unwrap();
+        assert_eq!(game.grid().disc(5, 2), Some(Disc { owner: Tile::RED, power: Some(Power::Anvil) }));
+        assert!(!game.grid().is_occupied(4, 2));
+        assert_eq!(game.check_move(Move::Power(Power::Anvil, 3)), Ok(()));
+        play_all(&mut game, &[0]);
+        assert_eq!(game.check_move(Move::Power(Power::Anvil, 3)), Err(MoveError::PowerUsed(Power::Anvil)));
+        // Undoing gives the anvil back
+        game.undo();
+        game.undo();
+        assert!(game.has_power(Tile::RED, Power::Anvil));
+        assert_eq!(game.grid().get(2, 2), Some(Tile::YELLOW));
+    }
+
+    #[test]
+    fn bomb_removes_its_target() {
+        let mut game = Game::new(rules(Variant::PowerUp));
+        play_all(&mut game, &[1, 1, 1]);
+        assert_eq!(game.check_move(Move::Bomb(2, Direction::East)), Err(MoveError::BadTarget(2, Direction::East)));
+        // Yellow's own disc is not a target
+        assert_eq!(game.check_move(Move::Bomb(0, Direction::NorthEast)), Err(MoveError::BadTarget(0, Direction::NorthEast)));
+        assert_eq!(game.check_move(Move::Power(Power::Bomb, 2)), Err(MoveError::NoTarget(2)));
+        game.play_move(Move::Bomb(2, Direction::West)).unwrap();
+        assert_eq!(game.grid().disc(5, 2), Some(Disc { owner: Tile::YELLOW, power: Some(Power::Bomb) }));
+        // The discs above the red one fell into its place
+        assert_eq!(game.grid().get(5, 1), Some(Tile::YELLOW));
+        assert_eq!(game.grid().get(4, 1), Some(Tile::RED));
+        assert!(!game.grid().is_occupied(3, 1));
+        assert!(!game.has_power(Tile::YELLOW, Power::Bomb));
+        assert!(game.has_power(Tile::RED, Power::Bomb));
+        assert!(!game.legal_moves().contains(&Move::Power(Power::Bomb, 3)));
+    }
+
+    #[test]
+    fn wall_counts_for_nobody() {
+        let mut game = Game::new(rules(Variant::PowerUp));
+        play_all(&mut game, &[0, 6, 1, 6]);
+        game.play_move(Move::Power(Power::Wall, 2)).unwrap();
+        play_all(&mut game, &[6, 3, 5]);
+        assert_eq!(game.grid().get(5, 2), None);
+        assert_eq!(game.grid().disc(5, 2), Some(Disc { owner: Tile::RED, power: Some(Power::Wall) }));
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+    }
+
+    #[test]
+    fn double_moves_again() {
+        let mut game = Game::new(rules(Variant::PowerUp));
+        game.play_move(Move::Power(Power::Double, 0)).unwrap();
+        assert_eq!(game.current_player(), Tile::RED);
+        play_all(&mut game, &[1]);
+        assert_eq!(game.current_player(), Tile::YELLOW);
+        assert_eq!(game.check_move(Move::Power(Power::Double, 0)), Ok(()));
+        game.undo();
+        game.undo();
+        assert_eq!(game.current_player(), Tile::RED);
+        assert!(game.has_power(Tile::RED, Power::Double));
+    }
+
+    #[test]
+    fn score_four_has_76_lines() {
+        let game = Game::new(Rules::SCORE_FOUR);
+        let mut lines = game.all_lines();
+        assert_eq!(lines.len(), 76);
+        for line in &mut lines {
+            line.sort();
+        }
+        lines.sort();
+        lines.dedup();
+        assert_eq!(lines.len(), 76);
+    }
+
+    #[test]
+    fn score_four_space_diagonal() {
+        // Red climbs from peg (0, 0) to peg (3, 3) on top of other discs
+        let mut grid = Grid::new(16, 4);
+        for k in 0..4 {
+            grid.set(3 - k, 5 * k, Some(Tile::RED));
+            for z in 0..k {
+                grid.set(3 - z, 5 * k, Some(Tile::YELLOW));
+            }
+        }
+        grid.set(2, 15, Some(Tile::RED));
+        let game = Game::from_position(Rules::SCORE_FOUR, grid, Tile::RED).unwrap();
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(3, 0), (2, 5), (1, 10), (0, 15)]));
+    }
+
+    #[test]
+    fn score_four_lines_stay_on_a_level() {
+        // Four in a row along a side of the square, but not four columns in a row of the grid
+        let mut game = Game::new(Rules::SCORE_FOUR);
+        play_all(&mut game, &[0, 1, 4, 1, 8, 1, 12]);
+        assert_eq!(game.win(), Some(Tile::RED));
+
+        let mut game = Game::new(Rules::SCORE_FOUR);
+        play_all(&mut game, &[2, 8, 3, 8, 4, 9, 5]);
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+    }
+
+    #[test]
+    fn rotated_turns_a_quarter() {
+        // 3 wide and 2 high, with a red disc in the bottom left and a yellow one next to it
+        let mut grid = Grid::new(3, 2);
+        grid.set(1, 0, Some(Tile::RED));
+        grid.set(1, 1, Some(Tile::YELLOW));
+
+        let clockwise = grid.rotated(Rotation::Clockwise);
+        assert_eq!((clockwise.cols(), clockwise.rows()), (2, 3));
+        assert_eq!(clockwise.get(0, 0), Some(Tile::RED));
+        assert_eq!(clockwise.get(1, 0), Some(Tile::YELLOW));
+        assert_eq!(clockwise.discs().count(), 2);
+
+        let anticlockwise = grid.rotated(Rotation::Anticlockwise);
+        assert_eq!(anticlockwise.get(2, 1), Some(Tile::RED));
+        assert_eq!(anticlockwise.get(1, 1), Some(Tile::YELLOW));
+
+        assert_eq!(clockwise.rotated(Rotation::Anticlockwise), grid);
+        let turned = (0..4).fold(grid.clone(), |grid, _| grid.rotated(Rotation::Clockwise));
+        assert_eq!(turned, grid);
+    }
+
+    #[test]
+    fn settle_keeps_the_order_of_discs() {
+        let mut grid = Grid::new(2, 4);
+        grid.set(0, 0, Some(Tile::RED));
+        grid.set(2, 0, Some(Tile::YELLOW));
+        grid.set(0, 1, Some(Tile::YELLOW));
+        grid.settle();
+        assert_eq!(grid.get(3, 0), Some(Tile::YELLOW));
+        assert_eq!(grid.get(2, 0), Some(Tile::RED));
+        assert!(!grid.is_occupied(1, 0));
+        assert_eq!(grid.get(3, 1), Some(Tile::YELLOW));
+        assert!(!grid.is_occupied(0, 1));
+    }
+
+    #[test]
+    fn gravity_flip_rotation() {
+        let mut game = Game::new(Rules::GRAVITY_FLIP);
+        play_all(&mut game, &[4, 2, 5, 3, 5, 6, 2, 5, 2, 0, 4, 5, 6, 0]);
+        let before = game.grid().clone();
+        game.play_move(Move::Rotate(Rotation::Clockwise)).unwrap();
+
+        let mut expected = before.rotated(Rotation::Clockwise);
+        expected.settle();
+        assert_eq!(*game.grid(), expected);
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(3, 1), (4, 1), (5, 1), (6, 1)]));
+
+        game.undo();
+        assert_eq!(*game.grid(), before);
+        assert_eq!(Game::default().check_move(Move::Rotate(Rotation::Clockwise)), Err(MoveError::Variant(Variant::Standard)));
+    }
+
+    #[test]
+    fn gravity_flip_threefold_repetition() {
+        let mut game = Game::new(Rules::GRAVITY_FLIP);
+        // Turning an empty board changes nothing but whose turn it is
+        for _ in 0..3 {
+            game.play_move(Move::Rotate(Rotation::Clockwise)).unwrap();
+        }
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        game.play_move(Move::Rotate(Rotation::Anticlockwise)).unwrap();
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(*game.outcome(), GameOutcome::Draw);
+    }
+
+    #[test]
+    fn misere_line_loses() {
+        let mut game = Game::new(Rules::STANDARD.with_scoring(Scoring::Misere));
+        play_all(&mut game, &[0, 0, 1, 1, 2, 2, 3]);
+        assert_eq!(*game.outcome(), GameOutcome::Loss(Tile::RED, vec![(5, 0), (5, 1), (5, 2), (5, 3)]));
+        assert_eq!(game.win(), None);
+        assert!(game.is_over());
+    }
+
+    #[test]
+    fn count_fours_plays_to_the_end() {
+        let rules = Rules::from_parts(4, 4, 4, Variant::Standard, 2, Scoring::CountFours).unwrap();
+        let mut game = Game::new(rules);
+        play_all(&mut game, &[0, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        assert_eq!(game.scores(), vec![1, 0]);
+
+        // Yellow answers with a line of its own, and nobody makes another
+        let mut tied = game.clone();
+        play_all(&mut tied, &[1, 2, 3, 3, 2, 2, 3, 3, 2]);
+        assert_eq!(tied.scores(), vec![1, 1]);
+        assert_eq!(*tied.outcome(), GameOutcome::Draw);
+
+        play_all(&mut game, &[2, 1, 3, 2, 3, 2, 3, 3, 2]);
+        assert_eq!(game.scores(), vec![1, 0]);
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(0, 0), (1, 0), (2, 0), (3, 0)]));
+    }
+
+    #[test]
+    fn from_position_errors() {
+        let rules = Rules::STANDARD;
+        assert_eq!(Game::from_position(rules, Grid::new(6, 6), Tile::RED).unwrap_err(), SetupError::BoardSize(6, 6));
+        assert_eq!(Game::from_position(rules, Grid::new(7, 6), Tile::GREEN).unwrap_err(), SetupError::Player(Tile::GREEN));
+
+        let mut grid = Grid::new(7, 6);
+        grid.set(5, 0, Some(Tile::BLUE));
+        assert_eq!(Game::from_position(rules, grid, Tile::RED).unwrap_err(), SetupError::Player(Tile::BLUE));
+
+        let mut grid = Grid::new(7, 6);
+        grid.set(3, 2, Some(Tile::RED));
+        grid.set(5, 2, Some(Tile::YELLOW));
+        assert_eq!(Game::from_position(rules, grid.clone(), Tile::RED).unwrap_err(), SetupError::Floating(3, 2));
+        // Without gravity discs can go anywhere
+        let free = rules.with_variant(Variant::FreePlacement).unwrap();
+        assert!(Game::from_position(free, grid, Tile::RED).is_ok());
+
+        let mut grid = Grid::new(7, 6);
+        grid.set(5, 0, Some(Tile::RED));
+        assert_eq!(Game::from_position(rules, grid.clone(), Tile::RED).unwrap_err(), SetupError::DiscCount(vec![1, 0], Tile::RED));
+        assert!(Game::from_position(rules, grid.clone(), Tile::YELLOW).is_ok());
+        // Pops can leave any number of discs
+        assert!(Game::from_position(rules.with_variant(Variant::PopOut).unwrap(), grid, Tile::RED).is_ok());
+
+        let three = rules.with_players(3).unwrap();
+        let mut grid = Grid::new(7, 6);
+        grid.set(5, 0, Some(Tile::RED));
+        grid.set(5, 1, Some(Tile::YELLOW));
+        assert!(Game::from_position(three, grid.clone(), Tile::GREEN).is_ok());
+        assert_eq!(Game::from_position(three, grid, Tile::RED).unwrap_err(), SetupError::DiscCount(vec![1, 1, 0], Tile::RED));
+    }
+
+    #[test]
+    fn from_position_lines() {
+        let mut grid = Grid::new(7, 6);
+        for col in 0..4 {
+            grid.set(5, col, Some(Tile::RED));
+            grid.set(4, col, Some(Tile::YELLOW));
+        }
+        assert_eq!(Game::from_position(Rules::STANDARD, grid.clone(), Tile::RED).unwrap_err(), SetupError::SeveralLines);
+        // Counting every line, both can have some
+        let count_fours = Rules::STANDARD.with_scoring(Scoring::CountFours);
+        let game = Game::from_position(count_fours, grid, Tile::RED).unwrap();
+        assert_eq!(game.scores(), vec![1, 1]);
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+
+        // A single line ends the game straight away
+        let mut grid = Grid::new(7, 6);
+        for col in 0..4 {
+            grid.set(5, col, Some(Tile::RED));
+        }
+        for col in 4..7 {
+            grid.set(5, col, Some(Tile::YELLOW));
+        }
+        let game = Game::from_position(Rules::STANDARD, grid, Tile::YELLOW).unwrap();
+        assert_eq!(game.win(), Some(Tile::RED));
+        assert!(game.history().is_empty());
+    }
+
+    #[test]
+    fn connect_five_on_a_larger_board() {
+        let rules = Rules::new(8, 7, 5).unwrap();
+        let mut game = Game::new(rules);
+        play_all(&mut game, &[0, 0, 1, 1, 2, 2, 3, 3]);
+        // Four in a row is not enough
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        game.play(4).unwrap();
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(6, 0), (6, 1), (6, 2), (6, 3), (6, 4)]));
+
+        // Lines reach the last row and column
+        let mut game = Game::new(rules);
+        play_all(&mut game, &[7, 6, 7, 6, 7, 6, 7, 6]);
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        game.play(7).unwrap();
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, (2..7).map(|row| (row, 7)).collect()));
+    }
+
+    #[test]
+    fn turns_go_round_every_player() {
+        for players in 3..=MAX_PLAYERS {
+            let mut game = Game::new(Rules::STANDARD.with_players(players).unwrap());
+            for turn in 0..players * 2 {
+                assert_eq!(game.current_player(), Tile::new(turn % players));
+                let row = game.play(turn % 7).unwrap();
+                assert_eq!(game.grid().get(row, turn % 7), Some(Tile::new(turn % players)));
+            }
+            game.undo();
+            assert_eq!(game.current_player(), Tile::new(players - 1));
+        }
+    }
+
+    #[test]
+    fn later_colours_can_win() {
+        let mut game = Game::new(Rules::STANDARD.with_players(3).unwrap());
+        play_all(&mut game, &[0, 1, 6, 1, 0, 6, 2, 3, 6, 3, 2]);
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        game.play(6).unwrap();
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::GREEN, vec![(2, 6), (3, 6), (4, 6), (5, 6)]));
+
+        let mut game = Game::new(Rules::STANDARD.with_players(4).unwrap());
+        play_all(&mut game, &[0, 1, 2, 6, 1, 2, 0, 6, 2, 0, 1, 6, 3, 4, 5]);
+        assert_eq!(game.current_player(), Tile::BLUE);
+        game.play(6).unwrap();
+        assert_eq!(game.win(), Some(Tile::BLUE));
+        assert_eq!(game.play(3), Err(MoveError::GameOver));
+    }
+
+    #[test]
+    fn from_position_after_a_rotation() {
+        // The rotation passed the turn without adding a disc
+        let rules = Rules::GRAVITY_FLIP;
+        let mut game = Game::new(rules);
+        game.play(2).unwrap();
+        game.play_move(Move::Rotate(Rotation::Clockwise)).unwrap();
+        let set_up = Game::from_position(rules, game.grid().clone(), game.current_player()).unwrap();
+        assert_eq!(set_up.grid(), game.grid());
+        assert_eq!(set_up.current_player(), Tile::RED);
+        assert_eq!(set_up.grid().discs().count(), 1);
+        // Still checked where every turn adds a disc
+        let standard = Rules::new(rules.cols, rules.rows, rules.connect).unwrap();
+        assert!(matches!(Game::from_position(standard, game.grid().clone(), Tile::RED), Err(SetupError::DiscCount(..))));
+    }
+
+    #[test]
+    fn spent_powers_carry_over() {
+        let rules = rules(Variant::PowerUp);
+        let game = Game::from_position(rules, Grid::new(7, 6), Tile::RED).unwrap()
+            .with_spent_powers(&[(Tile::YELLOW, Power::Bomb)]);
+        assert_eq!(game.spent_powers(), vec![(Tile::YELLOW, Power::Bomb)]);
+        assert!(game.has_power(Tile::RED, Power::Bomb));
+        assert!(!game.has_power(Tile::YELLOW, Power::Bomb));
+    }
+
+    #[test]
+    fn place_moves_parse_and_display() {
+        assert_eq!("4:3".parse(), Ok(Move::Place(2, 3)));
+        assert_eq!(Move::Place(2, 3).to_string(), "4:3");
+        for mv in [Move::Place(0, 0), Move::Place(5, 6), Move::Place(11, 14)] {
+            assert_eq!(mv.to_string().parse(), Ok(mv));
+        }
+        for bad in ["0:3", "4:0", "4:", ":3", "a:3", "4:3:1"] {
+            assert_eq!(bad.parse::<Move>(), Err(()), "{bad}");
+        }
+    }
+
+    #[test]
+    fn place_errors() {
+        let mut game = Game::new(rules(Variant::FreePlacement));
+        game.play_move(Move::Place(2, 3)).unwrap();
+        assert_eq!(game.check_move(Move::Place(2, 3)), Err(MoveError::Occupied(2, 3)));
+        assert_eq!(game.check_move(Move::Place(6, 3)), Err(MoveError::RowOutOfBounds(6)));
+        assert_eq!(game.check_move(Move::Place(0, 7)), Err(MoveError::OutOfBounds(7)));
+        assert_eq!(game.check_move(Move::Drop(3)), Err(MoveError::Variant(Variant::FreePlacement)));
+        assert_eq!(Game::default().check_move(Move::Place(5, 3)), Err(MoveError::Variant(Variant::Standard)));
+    }
+
+    #[test]
+    fn free_placement_win_without_gravity() {
+        let mut game = Game::new(rules(Variant::FreePlacement));
+        for mv in [(0, 0), (3, 6), (0, 1), (2, 6), (0, 2), (1, 5)] {
+            game.play_move(Move::Place(mv.0, mv.1)).unwrap();
+        }
+        // Nothing fell
+        assert_eq!(game.grid().get(0, 1), Some(Tile::RED));
+        assert!(!game.grid().is_occupied(5, 1));
+        assert_eq!(*game.outcome(), GameOutcome::InProgress);
+        game.play_move(Move::Place(0, 3)).unwrap();
+        assert_eq!(*game.outcome(), GameOutcome::Win(Tile::RED, vec![(0, 0), (0, 1), (0, 2), (0, 3)]));
+    }
+
+    #[test]
+    fn fog_hides_distant_opponent_discs() {
+        let mut game = Game::new(rules(Variant::FogOfWar));
+        play_all(&mut game, &[0, 1, 3, 6]);
+        // Own discs, and opponent discs next to them
+        assert!(game.is_visible(5, 0, Tile::RED));
+        assert!(game.is_visible(5, 1, Tile::RED));
+        assert!(game.is_visible(5, 0, Tile::YELLOW));
+        // Opponent discs further away
+        assert!(!game.is_visible(5, 6, Tile::RED));
+        assert!(!game.is_visible(5, 3, Tile::YELLOW));
+        // Empty cells show nothing to hide
+        assert!(game.is_visible(4, 6, Tile::RED));
+
+        // Diagonal neighbours count too
+        play_all(&mut game, &[5, 6]);
+        assert!(game.is_visible(5, 6, Tile::RED));
+        assert!(game.is_visible(4, 6, Tile::RED));
+
+        let mut standard = Game::default();
+        play_all(&mut standard, &[0, 6]);
+        assert!(standard.is_visible(5, 6, Tile::RED));
+    }
+
+    #[test]
+    fn fog_lifts_when_the_game_ends() {
+        let mut game = Game::new(rules(Variant::FogOfWar));
+        play_all(&mut game, &[0, 6, 1, 6, 2, 6]);
+        assert!(!game.is_visible(3, 6, Tile::RED));
+        game.play(3).unwrap();
+        assert!(game.is_over());
+        assert!(game.is_visible(3, 6, Tile::RED));
+    }
+
+    #[test]
+    fn completes_line_matches_playing() {
+        let variants = [Variant::Standard, Variant::Cylinder, Variant::ScoreFour, Variant::FreePlacement];
+        for (i, variant) in variants.into_iter().enumerate() {
+            let rules = if variant == Variant::ScoreFour { Rules::SCORE_FOUR } else { rules(variant) };
+            let mut game = Game::new(rules);
+            while !game.is_over() {
+                for mv in game.legal_moves() {
+                    let (row, col) = match mv {
+                        Move::Place(row, col) => (row, col),
+                        _ => (game.grid().drop_row(mv.col().unwrap()).unwrap(), mv.col().unwrap())
+                    };
+                    let mut next = game.clone();
+                    next.play_move(mv).unwrap();
+                    let tile = game.current_player();
+                    assert_eq!(game.completes_line(row, col, tile), next.win() == Some(tile), "{variant} {mv}");
+                }
+                let moves = game.legal_moves();
+                game.play_move(moves[(game.history().len() * 5 + i) % moves.len()]).unwrap();
+            }
+        }
+
+        // Wrapping round, but not reusing a column
+        let rules = Rules::new(4, 6, 4).unwrap().with_variant(Variant::Cylinder).unwrap();
+        let mut game = Game::new(rules);
+        play_all(&mut game, &[2, 2, 3, 3, 0]);
+        assert!(!game.completes_line(5, 1, Tile::YELLOW));
+        assert!(game.completes_line(5, 1, Tile::RED));
+        let game = Game::new(Rules::new(3, 6, 4).unwrap().with_variant(Variant::Cylinder).unwrap());
+        assert!(!game.completes_line(5, 0, Tile::RED));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod game;
+pub mod bitboard;
+pub mod ai;
+pub mod solver;
+pub mod book;
+pub mod record;
+pub mod player;
+pub mod engine;
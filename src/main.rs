@@ -1,14 +1,178 @@
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use c4::{ai::Difficulty, book::Book, game::{MAX_PLAYERS, Move, Power, Rotation, Rules, Tile}};
+use replay::ReplayCommand;
 use state::State;
-use winit::{application::ApplicationHandler, event::{ElementState, MouseButton, WindowEvent}, event_loop::{ControlFlow, EventLoop}, keyboard::{KeyCode, PhysicalKey}, window::Window};
+use winit::{application::ApplicationHandler, event::{ElementState, MouseButton, WindowEvent}, event_loop::{ControlFlow, EventLoop}, keyboard::{KeyCode, ModifiersState, PhysicalKey}, window::Window};
 
 mod camera;
 mod state;
 mod board;
 mod skybox;
+mod replay;
+mod editor;
+mod analysis;
+
+// Command line options
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub book: Option<Arc<Book>>,
+    pub record_path: PathBuf,
+    // Open the record in replay mode on startup
+    pub start_replay: bool,
+    pub rules: Rules,
+    // Disc colours replacing the defaults, in turn order
+    pub colors: Vec<[f32; 4]>,
+    // Who plays each colour, in turn order
+    pub seats: Vec<PlayerKind>,
+    // Thinking time given to engine programs
+    pub movetime: Duration
+}
+
+// How the moves for one colour are made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerKind {
+    Human,
+    Computer,
+    Script(Vec<Move>),
+    // Play against a peer listening at HOST:PORT, or wait for one to connect to PORT
+    Connect(String),
+    Listen(u16),
+    // An engine program and its arguments
+    Engine(Vec<String>)
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            book: None,
+            record_path: PathBuf::from("game.c4"),
+            start_replay: false,
+            rules: Rules::STANDARD,
+            colors: Vec::new(),
+            seats: vec![PlayerKind::Human; MAX_PLAYERS],
+            movetime: Duration::from_secs(1)
+        }
+    }
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut opts = Self::default();
+        let Rules { mut cols, mut rows, mut connect, mut variant, mut players, mut scoring } = opts.rules;
+        let mut sized = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--book" => {
+                    let path = args.next().context("--book needs a path")?;
+                    let book = Book::load(&path).with_context(|| format!("failed to load book {path}"))?;
+                    println!("Loaded {} book positions", book.len());
+                    opts.book = Some(Arc::new(book));
+                },
+                "--record" => {
+                    opts.record_path = args.next().context("--record needs a path")?.into();
+                },
+                "--replay" => {
+                    opts.record_path = args.next().context("--replay needs a path")?.into();
+                    opts.start_replay = true;
+                },
+                "--size" => {
+                    let size = args.next().context("--size needs COLSxROWS")?;
+                    (cols, rows) = size.split_once('x')
+                        .and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)))
+                        .with_context(|| format!("invalid board size {size}"))?;
+                    sized = true;
+                },
+                "--connect" => {
+                    connect = args.next().context("--connect needs a length")?.parse().context("invalid connect length")?;
+                },
+                "--players" => {
+                    players = args.next().context("--players needs a number")?.parse().context("invalid number of players")?;
+                },
+                "--colors" => {
+                    let colors = args.next().context("--colors needs a comma-separated list")?;
+                    opts.colors = colors.split(',')
+                        .map(|color| parse_color(color).with_context(|| format!("invalid colour {color}")))
+                        .collect::<anyhow::Result<_>>()?;
+                    if opts.colors.len() > MAX_PLAYERS {
+                        bail!("at most {MAX_PLAYERS} colours can be given");
+                    }
+                },
+                "--variant" => {
+                    variant = args.next().context("--variant needs a name")?.parse()?;
+                },
+                "--scoring" => {
+                    scoring = args.next().context("--scoring needs a name")?.parse()?;
+                },
+                "--player" => {
+                    let seat = args.next().context("--player needs COLOUR=PLAYER")?;
+                    let (color, kind) = seat.split_once('=').with_context(|| format!("invalid player {seat}"))?;
+                    let id = (0..MAX_PLAYERS)
+                        .find(|&id| Tile::new(id).to_string().eq_ignore_ascii_case(color))
+                        .with_context(|| format!("unknown colour {color}"))?;
+                    opts.seats[id] = parse_player(kind)?;
+                },
+                "--movetime" => {
+                    let ms = args.next().context("--movetime needs milliseconds")?.parse().context("invalid move time")?;
+                    opts.movetime = Duration::from_millis(ms);
+                },
+                _ => bail!("unknown argument {arg}")
+            }
+        }
 
-#[derive(Debug, Default)]
+        // Score Four boards are given as PEGSxHEIGHT
+        if !sized {
+            (cols, rows) = variant.default_size();
+        }
+        opts.rules = Rules::from_parts(cols, rows, connect, variant, players, scoring)?;
+        Ok(opts)
+    }
+}
+
+// human, computer, script:MOVE,MOVE,..., connect:HOST:PORT, listen:PORT or
+// engine:PROGRAM ARGS...
+fn parse_player(s: &str) -> anyhow::Result<PlayerKind> {
+    let kind = match s.split_once(':') {
+        None if s == "human" => PlayerKind::Human,
+        None if s == "computer" => PlayerKind::Computer,
+        Some(("script", moves)) => PlayerKind::Script(moves.split(',')
+            .map(|mv| mv.parse().map_err(|()| anyhow!("invalid move {mv}")))
+            .collect::<anyhow::Result<_>>()?),
+        Some(("connect", addr)) => PlayerKind::Connect(addr.to_owned()),
+        Some(("listen", port)) => PlayerKind::Listen(port.parse().with_context(|| format!("invalid port {port}"))?),
+        Some(("engine", command)) if !command.trim().is_empty() => PlayerKind::Engine(command.split_whitespace().map(str::to_owned).collect()),
+        _ => bail!("unknown player {s}")
+    };
+    Ok(kind)
+}
+
+// A colour name or #rrggbb
+fn parse_color(s: &str) -> Option<[f32; 4]> {
+    let rgb = match s.to_ascii_lowercase().as_str() {
+        "red" => 0xff0000,
+        "yellow" => 0xffff00,
+        "green" => 0x00cc00,
+        "blue" => 0x2673ff,
+        "orange" => 0xff8c00,
+        "purple" => 0x9628dc,
+        "white" => 0xffffff,
+        "black" => 0x141414,
+        hex => {
+            let hex = hex.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+            u32::from_str_radix(hex, 16).ok()?
+        }
+    };
+    let channel = |shift: u32| ((rgb >> shift) & 0xff) as f32 / 255.;
+    Some([channel(16), channel(8), channel(0), 1.])
+}
+
+#[derive(Debug)]
 struct App {
-    state: Option<State>
+    state: Option<State>,
+    opts: Options,
+    modifiers: ModifiersState
 }
 
 impl ApplicationHandler for App {
@@ -17,7 +181,12 @@ impl ApplicationHandler for App {
             .with_title("Connect 4")
             .with_visible(false)
         ).unwrap();
-        self.state = Some(State::new(win));
+        let mut state = State::new(win, &self.opts);
+        if self.opts.start_replay {
+            state.load_game();
+            state.toggle_replay();
+        }
+        self.state = Some(state);
     }
 
     fn window_event(
@@ -40,8 +209,27 @@ impl ApplicationHandler for App {
                 state.win().request_redraw();
                 state.render();
             },
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            },
             WindowEvent::KeyboardInput { event, .. } => {
                 match event.physical_key {
+                    PhysicalKey::Code(KeyCode::KeyZ) if event.state.is_pressed() && self.modifiers.control_key() => {
+                        let state = self.state.as_mut().unwrap();
+                        state.undo();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyY) if event.state.is_pressed() && self.modifiers.control_key() => {
+                        let state = self.state.as_mut().unwrap();
+                        state.redo();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyS) if event.state.is_pressed() && !event.repeat && self.modifiers.control_key() => {
+                        let state = self.state.as_mut().unwrap();
+                        state.save_game();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyO) if event.state.is_pressed() && !event.repeat && self.modifiers.control_key() => {
+                        let state = self.state.as_mut().unwrap();
+                        state.load_game();
+                    },
                     PhysicalKey::Code(KeyCode::ArrowLeft) => {
                         let state = self.state.as_mut().unwrap();
                         state.horiz_left = event.state.is_pressed();
@@ -50,6 +238,85 @@ impl ApplicationHandler for App {
                         let state = self.state.as_mut().unwrap();
                         state.horiz_right = event.state.is_pressed();
                     },
+                    PhysicalKey::Code(KeyCode::KeyN) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.new_game();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyR) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.toggle_replay();
+                    },
+                    PhysicalKey::Code(code @ (KeyCode::Period | KeyCode::Comma | KeyCode::Home | KeyCode::End | KeyCode::Space | KeyCode::Equal | KeyCode::Minus)) if event.state.is_pressed() => {
+                        let state = self.state.as_mut().unwrap();
+                        let cmd = match code {
+                            KeyCode::Period => ReplayCommand::Forward,
+                            KeyCode::Comma => ReplayCommand::Back,
+                            KeyCode::Home => ReplayCommand::Start,
+                            KeyCode::End => ReplayCommand::End,
+                            KeyCode::Space => ReplayCommand::ToggleAutoplay,
+                            KeyCode::Equal => ReplayCommand::Faster,
+                            _ => ReplayCommand::Slower
+                        };
+                        state.replay_command(cmd);
+                    },
+                    PhysicalKey::Code(KeyCode::KeyV) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.toggle_cylinder_view();
+                    },
+                    PhysicalKey::Code(code @ (KeyCode::KeyA | KeyCode::KeyB | KeyCode::KeyW | KeyCode::KeyD)) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        let power = match code {
+                            KeyCode::KeyA => Power::Anvil,
+                            KeyCode::KeyB => Power::Bomb,
+                            KeyCode::KeyW => Power::Wall,
+                            _ => Power::Double
+                        };
+                        state.select_power(power);
+                    },
+                    PhysicalKey::Code(KeyCode::Tab) if event.state.is_pressed() => {
+                        let state = self.state.as_mut().unwrap();
+                        state.cycle_bomb_target();
+                    },
+                    PhysicalKey::Code(KeyCode::Enter) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.confirm_bomb();
+                    },
+                    PhysicalKey::Code(KeyCode::Escape) if event.state.is_pressed() => {
+                        let state = self.state.as_mut().unwrap();
+                        state.cancel();
+                    },
+                    PhysicalKey::Code(code @ (KeyCode::KeyQ | KeyCode::KeyE)) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        let rotation = if code == KeyCode::KeyQ { Rotation::Anticlockwise } else { Rotation::Clockwise };
+                        state.rotate_board(rotation);
+                    },
+                    PhysicalKey::Code(KeyCode::KeyP) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.toggle_editor();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyT) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.cycle_to_move();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyH) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.toggle_hints();
+                    },
+                    PhysicalKey::Code(KeyCode::KeyC) if event.state.is_pressed() && !event.repeat => {
+                        let state = self.state.as_mut().unwrap();
+                        state.cycle_ai_player();
+                    },
+                    PhysicalKey::Code(code @ (KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 | KeyCode::Digit4 | KeyCode::Digit5)) if event.state.is_pressed() => {
+                        let state = self.state.as_mut().unwrap();
+                        let level = match code {
+                            KeyCode::Digit1 => 0,
+                            KeyCode::Digit2 => 1,
+                            KeyCode::Digit3 => 2,
+                            KeyCode::Digit4 => 3,
+                            _ => 4
+                        };
+                        state.set_difficulty(Difficulty::ALL[level]);
+                    },
                     _ => {}
                 }
             },
@@ -60,8 +327,10 @@ impl ApplicationHandler for App {
             WindowEvent::MouseInput { button, state: estate, .. } => {
                 let state = self.state.as_mut().unwrap();
                 
-                if button == MouseButton::Left && estate == ElementState::Pressed {
-                    state.mouse_click();
+                match (button, estate) {
+                    (MouseButton::Left, ElementState::Pressed) => state.mouse_click(),
+                    (MouseButton::Right, ElementState::Pressed) => state.mouse_right_click(),
+                    _ => {}
                 }
             }
             _ => {}
@@ -75,9 +344,15 @@ impl ApplicationHandler for App {
 }
 
 fn main() -> anyhow::Result<()> {
+    let mut app = App {
+        state: None,
+        opts: Options::parse(env::args().skip(1))?,
+        modifiers: ModifiersState::default()
+    };
+
     let ev = EventLoop::new()?;
     ev.set_control_flow(ControlFlow::Poll);
-    ev.run_app(&mut App::default())?;
+    ev.run_app(&mut app)?;
 
     Ok(())
 }
@@ -0,0 +1,335 @@
+// Whoever makes the moves for one colour: a person at this computer, the built-in AI,
+// a fixed list of moves or a peer over the network
+
+use std::{collections::VecDeque, error::Error, fmt, io::{self, Read, Write}, net::{TcpListener, TcpStream, ToSocketAddrs}, sync::Arc};
+
+use crate::{ai::{Ai, Difficulty, Worker}, bitboard::{Position, PositionError}, book::Book, engine::{EngineError, Info}, game::{Game, Move, Tile}};
+
+#[derive(Debug)]
+pub enum PlayerError {
+    // The built-in AI only plays the standard game
+    Position(PositionError),
+    // A scripted player has no moves left
+    ScriptEnded,
+    Disconnected,
+    // A line from a remote peer or an engine that is not a move
+    BadMessage(String),
+    Engine(EngineError),
+    Io(io::Error)
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerError::Position(e) => write!(f, "{e}"),
+            PlayerError::ScriptEnded => write!(f, "the script has run out of moves"),
+            PlayerError::Disconnected => write!(f, "the remote player disconnected"),
+            PlayerError::BadMessage(line) => write!(f, "unexpected message {line:?}"),
+            PlayerError::Engine(e) => write!(f, "{e}"),
+            PlayerError::Io(e) => write!(f, "{e}")
+        }
+    }
+}
+
+impl Error for PlayerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PlayerError::Position(e) => Some(e),
+            PlayerError::Engine(e) => Some(e),
+            PlayerError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for PlayerError {
+    fn from(e: io::Error) -> Self {
+        PlayerError::Io(e)
+    }
+}
+
+impl From<EngineError> for PlayerError {
+    fn from(e: EngineError) -> Self {
+        PlayerError::Engine(e)
+    }
+}
+
+impl From<PositionError> for PlayerError {
+    fn from(e: PositionError) -> Self {
+        PlayerError::Position(e)
+    }
+}
+
+pub trait Player: fmt::Debug {
+    // Name for game records
+    fn name(&self) -> String;
+
+    // Asked for a move each frame while it is this player's turn, until it has one
+    fn poll_move(&mut self, game: &Game) -> Result<Option<Move>, PlayerError>;
+
+    // Told about every move, its own included, once it has been played
+    fn move_played(&mut self, _game: &Game, _mv: Move) -> Result<(), PlayerError> {
+        Ok(())
+    }
+
+    // People pick their moves with the mouse and keyboard, which are passed on with `input`
+    fn is_human(&self) -> bool {
+        false
+    }
+
+    fn input(&mut self, _mv: Move) {}
+
+    // A game shared with someone elsewhere cannot be taken back or replaced
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    // The evaluation behind the player's last move, for players that report one
+    fn info(&self) -> Option<&Info> {
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Human {
+    pending: Option<Move>
+}
+
+impl Human {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Player for Human {
+    fn name(&self) -> String {
+        "Human".to_owned()
+    }
+
+    fn poll_move(&mut self, _game: &Game) -> Result<Option<Move>, PlayerError> {
+        Ok(self.pending.take())
+    }
+
+    fn is_human(&self) -> bool {
+        true
+    }
+
+    fn input(&mut self, mv: Move) {
+        self.pending = Some(mv);
+    }
+}
+
+// The built-in AI, searching on its own thread so that slow searches do not hold up
+// the caller
+#[derive(Debug)]
+pub struct Computer {
+    difficulty: Difficulty,
+    worker: Worker<Option<usize>>,
+    requested: Option<Position>
+}
+
+impl Computer {
+    pub fn new(difficulty: Difficulty, book: Option<Arc<Book>>) -> Self {
+        let mut ai = Ai::new(difficulty);
+        ai.set_book(book);
+        let worker = Worker::spawn(ai, |ai, pos| ai.choose_move(pos));
+        Self { difficulty, worker, requested: None }
+    }
+}
+
+impl Player for Computer {
+    fn name(&self) -> String {
+        format!("Computer ({:?})", self.difficulty)
+    }
+
+    fn poll_move(&mut self, game: &Game) -> Result<Option<Move>, PlayerError> {
+        let pos = Position::try_from(game)?;
+        if self.requested != Some(pos) {
+            self.worker.request(pos);
+            self.requested = Some(pos);
+        }
+        // Answers for positions that were left before the search finished are stale
+        while let Some((searched, col)) = self.worker.try_result() {
+            if searched == pos && col.is_some() {
+                // Coming back to this position later needs a new search
+                self.requested = None;
+                return Ok(col.map(Move::Drop));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Plays a fixed list of moves, such as an opening to practise against
+#[derive(Debug)]
+pub struct Scripted {
+    moves: VecDeque<Move>
+}
+
+impl Scripted {
+    pub fn new(moves: impl IntoIterator<Item = Move>) -> Self {
+        Self { moves: moves.into_iter().collect() }
+    }
+}
+
+impl Player for Scripted {
+    fn name(&self) -> String {
+        "Script".to_owned()
+    }
+
+    fn poll_move(&mut self, _game: &Game) -> Result<Option<Move>, PlayerError> {
+        self.moves.pop_front().map(Some).ok_or(PlayerError::ScriptEnded)
+    }
+}
+
+// A peer running its own copy of the game, with every move sent as a line of text in
+// record notation. Each side plays its own colours and sees the other's as remote
+#[derive(Debug)]
+pub struct Remote {
+    // None while waiting on `listener` for the peer to connect
+    stream: Option<TcpStream>,
+    listener: Option<TcpListener>,
+    // The colour the peer plays, whose moves are not sent back to it
+    tile: Tile,
+    received: Vec<u8>,
+    // Moves played before the peer connected
+    unsent: String
+}
+
+impl Remote {
+    pub fn connect(addr: impl ToSocketAddrs, tile: Tile) -> io::Result<Self> {
+        let mut remote = Self { stream: None, listener: None, tile, received: Vec::new(), unsent: String::new() };
+        remote.connected(TcpStream::connect(addr)?)?;
+        Ok(remote)
+    }
+
+    // Waits for the peer to connect in the background, while moves are polled
+    pub fn listen(port: u16, tile: Tile) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { stream: None, listener: Some(listener), tile, received: Vec::new(), unsent: String::new() })
+    }
+
+    fn connected(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        // Moves made before the peer connected are sent in full first; accepted streams can
+        // inherit the listener's nonblocking mode
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.write_all(self.unsent.as_bytes())?;
+        self.unsent.clear();
+        // Polled every frame, so reads must not wait for the peer
+        stream.set_nonblocking(true)?;
+        self.stream = Some(stream);
+        self.listener = None;
+        Ok(())
+    }
+
+    // Takes the peer's connection if it has come in
+    fn accept(&mut self) -> io::Result<()> {
+        let Some(listener) = &self.listener else {
+            return Ok(());
+        };
+        match listener.accept() {
+            Ok((stream, _)) => self.connected(stream),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+impl Player for Remote {
+    fn name(&self) -> String {
+        match self.stream.as_ref().map(TcpStream::peer_addr) {
+            Some(Ok(addr)) => format!("Remote ({addr})"),
+            _ => "Remote".to_owned()
+        }
+    }
+
+    fn poll_move(&mut self, _game: &Game) -> Result<Option<Move>, PlayerError> {
+        self.accept()?;
+        let Some(stream) = &mut self.stream else {
+            return Ok(None);
+        };
+        let mut buf = [0; 256];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(PlayerError::Disconnected),
+                Ok(n) => self.received.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into())
+            }
+        }
+
+        let Some(end) = self.received.iter().position(|&b| b == b'\n') else {
+            return Ok(None);
+        };
+        let line: Vec<_> = self.received.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line).trim().to_owned();
+        line.parse().map(Some).map_err(|()| PlayerError::BadMessage(line))
+    }
+
+    fn move_played(&mut self, game: &Game, mv: Move) -> Result<(), PlayerError> {
+        if game.last_mover() == Some(self.tile) {
+            return Ok(());
+        }
+        self.accept()?;
+        match &mut self.stream {
+            Some(stream) => writeln!(stream, "{mv}")?,
+            None => self.unsent.push_str(&format!("{mv}\n"))
+        }
+        Ok(())
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::{Duration, Instant}};
+
+    use super::*;
+
+    // Polls until the player moves, as the game loop does
+    fn wait_for_move(player: &mut dyn Player, game: &Game) -> Move {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(mv) = player.poll_move(game).unwrap() {
+                return mv;
+            }
+            assert!(Instant::now() < deadline, "no move from {}", player.name());
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn computer_searches_in_the_background() {
+        let mut computer = Computer::new(Difficulty::Easy, None);
+        let mut game = Game::default();
+        let mv = wait_for_move(&mut computer, &game);
+        assert!(game.is_legal(mv));
+        game.play_move(mv).unwrap();
+        game.play(0).unwrap();
+        assert!(game.is_legal(wait_for_move(&mut computer, &game)));
+    }
+
+    #[test]
+    fn remote_listens_without_blocking() {
+        let mut host = Remote::listen(0, Tile::YELLOW).unwrap();
+        let port = host.listener.as_ref().unwrap().local_addr().unwrap().port();
+        let mut game = Game::default();
+        game.play(3).unwrap();
+        // Moves made before the peer connects are sent once it does
+        host.move_played(&game, Move::Drop(3)).unwrap();
+        assert_eq!(host.poll_move(&game).unwrap(), None);
+
+        let mut guest = Remote::connect(("127.0.0.1", port), Tile::RED).unwrap();
+        assert_eq!(host.poll_move(&game).unwrap(), None);
+        assert!(host.stream.is_some());
+        assert_eq!(wait_for_move(&mut guest, &Game::default()), Move::Drop(3));
+        game.play(4).unwrap();
+        guest.move_played(&game, Move::Drop(4)).unwrap();
+        assert_eq!(wait_for_move(&mut host, &game), Move::Drop(4));
+    }
+}
@@ -0,0 +1,467 @@
+// Text game records, in the spirit of PGN:
+//
+//   [Red "Alice"]
+//   [Yellow "Computer (Hard)"]
+//   [Date "2026.10.18"]
+//   [Variant "Standard"]
+//   [Size "7x6"]
+//   [Connect "4"]
+//   [Result "1-0"]
+//
+//   1. 4 4 2. 3 5 3. 2 1 4. 1 1-0
+//
+// Moves are 1-based column numbers, prefixed with "p" for PopOut pops and with "a",
+// "b", "w" or "d" for Power Up discs; bombs also give the direction of their target,
+// as in "b4sw". Gravity Flip rotations are "cw" and "ccw", and free placements give the
+// column then the row counted from the top, as in "4:2". The result is "1-0" if Red won,
+// "0-1" if Yellow won, "1/2-1/2" for a draw and "*" for an unfinished game. Games with
+// more players have a Players header, a name header per player and a score per player in
+// the result, as in "0-0-1". Misère and count-all-fours games have a Scoring header; a
+// misère loser scores 0 and the other players share the win.
+
+use std::{error::Error, fmt, fs, io, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::game::{Game, GameOutcome, Move, MoveError, Rules, RulesError, Scoring};
+
+#[derive(Debug)]
+pub enum RecordErrorKind {
+    Io(io::Error),
+    BadHeader,
+    UnknownVariant(String),
+    UnknownScoring(String),
+    BoardSize(String),
+    Connect(String),
+    Players(String),
+    Rules(RulesError),
+    BadToken(String),
+    // The n-th move (1-based) of the game is illegal
+    IllegalMove { number: usize, mv: Move, error: MoveError },
+    ResultMismatch { recorded: String, actual: String }
+}
+
+// Line 0 refers to the file as a whole
+#[derive(Debug)]
+pub struct RecordError {
+    pub line: usize,
+    pub kind: RecordErrorKind
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: ", self.line)?;
+        }
+        match &self.kind {
+            RecordErrorKind::Io(e) => write!(f, "{e}"),
+            RecordErrorKind::BadHeader => write!(f, "malformed header"),
+            RecordErrorKind::UnknownVariant(v) => write!(f, "unknown variant {v:?}"),
+            RecordErrorKind::UnknownScoring(s) => write!(f, "unknown scoring {s:?}"),
+            RecordErrorKind::BoardSize(s) => write!(f, "invalid board size {s:?}, expected columns x rows"),
+            RecordErrorKind::Connect(s) => write!(f, "invalid connect length {s:?}"),
+            RecordErrorKind::Players(s) => write!(f, "invalid number of players {s:?}"),
+            RecordErrorKind::Rules(e) => write!(f, "{e}"),
+            RecordErrorKind::BadToken(t) => write!(f, "unexpected {t:?} in move list"),
+            RecordErrorKind::IllegalMove { number, mv, error } => write!(f, "move {number} ({mv}): {error}"),
+            RecordErrorKind::ResultMismatch { recorded, actual } => write!(f, "recorded result {recorded} but the moves give {actual}")
+        }
+    }
+}
+
+impl Error for RecordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            RecordErrorKind::Io(e) => Some(e),
+            RecordErrorKind::IllegalMove { error, .. } => Some(error),
+            RecordErrorKind::Rules(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for RecordError {
+    fn from(e: io::Error) -> Self {
+        RecordError { line: 0, kind: RecordErrorKind::Io(e) }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameRecord {
+    headers: Vec<(String, String)>,
+    rules: Rules,
+    moves: Vec<Move>
+}
+
+impl GameRecord {
+    // `names` are the players' names in turn order
+    pub fn from_game(game: &Game, names: &[&str]) -> Self {
+        let rules = game.rules();
+        let mut record = Self { headers: Vec::new(), rules, moves: game.history().to_vec() };
+        for (tile, name) in rules.turn_order().zip(names) {
+            record.set_header(&tile.to_string(), name);
+        }
+        record.set_header("Date", &today());
+        record.set_header("Variant", &rules.variant.to_string());
+        if rules.players != 2 {
+            record.set_header("Players", &rules.players.to_string());
+        }
+        if rules.scoring != Scoring::Normal {
+            record.set_header("Scoring", &rules.scoring.to_string());
+        }
+        record.set_header("Size", &format!("{}x{}", rules.cols, rules.rows));
+        record.set_header("Connect", &rules.connect.to_string());
+        record.set_header("Result", &result_string(game.outcome(), rules.players));
+        record
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        match self.headers.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.headers.push((key.to_owned(), value.to_owned()))
+        }
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    // Replays the moves, which `parse` has already validated
+    pub fn to_game(&self) -> Game {
+        let mut game = Game::new(self.rules);
+        for &mv in &self.moves {
+            game.play_move(mv).expect("record moves are validated when parsed");
+        }
+        game
+    }
+}
+
+impl std::str::FromStr for GameRecord {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut record = GameRecord::default();
+        let Rules { mut connect, mut variant, mut players, mut scoring, .. } = Rules::STANDARD;
+        // The variant's usual size unless given
+        let mut size = None;
+        let mut rules_line = 0;
+        // Created once the headers have been read
+        let mut game = None;
+        let mut result = None;
+        let mut result_line = 0;
+        let mut header_line = 0;
+
+        for (i, line) in s.lines().enumerate() {
+            let line_no = i + 1;
+            let err = |kind| RecordError { line: line_no, kind };
+            let line = line.trim();
+
+            if let Some(header) = line.strip_prefix('[') {
+                if !record.moves.is_empty() || result.is_some() {
+                    return Err(err(RecordErrorKind::BadHeader));
+                }
+                let (key, value) = parse_header(header).ok_or(err(RecordErrorKind::BadHeader))?;
+                match key {
+                    "Variant" => {
+                        variant = value.parse().map_err(|_| err(RecordErrorKind::UnknownVariant(value.to_owned())))?;
+                        rules_line = line_no;
+                    },
+                    "Size" => {
+                        size = Some(value.split_once('x')
+                            .and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)))
+                            .ok_or(err(RecordErrorKind::BoardSize(value.to_owned())))?);
+                        rules_line = line_no;
+                    },
+                    "Connect" => {
+                        connect = value.parse().map_err(|_| err(RecordErrorKind::Connect(value.to_owned())))?;
+                        rules_line = line_no;
+                    },
+                    "Players" => {
+                        players = value.parse().map_err(|_| err(RecordErrorKind::Players(value.to_owned())))?;
+                        rules_line = line_no;
+                    },
+                    "Scoring" => {
+                        scoring = value.parse().map_err(|_| err(RecordErrorKind::UnknownScoring(value.to_owned())))?;
+                        rules_line = line_no;
+                    },
+                    "Result" => header_line = line_no,
+                    _ => {}
+                }
+                record.set_header(key, value);
+                continue;
+            }
+
+            for token in line.split_whitespace() {
+                let game = match &mut game {
+                    Some(game) => game,
+                    None => {
+                        let (cols, rows) = size.unwrap_or(variant.default_size());
+                        let rules = Rules::from_parts(cols, rows, connect, variant, players, scoring)
+                            .map_err(|e| RecordError { line: rules_line, kind: RecordErrorKind::Rules(e) })?;
+                        game.insert(Game::new(rules))
+                    }
+                };
+                if result.is_some() {
+                    return Err(err(RecordErrorKind::BadToken(token.to_owned())));
+                }
+                if is_result(token) {
+                    result = Some(token);
+                    result_line = line_no;
+                    continue;
+                }
+                if token.strip_suffix('.').is_some_and(|n| n.parse::<usize>().is_ok()) {
+                    // Move number
+                    continue;
+                }
+
+                let mv = token.parse::<Move>().map_err(|_| err(RecordErrorKind::BadToken(token.to_owned())))?;
+                let number = record.moves.len() + 1;
+                game.play_move(mv).map_err(|error| err(RecordErrorKind::IllegalMove { number, mv, error }))?;
+                record.moves.push(mv);
+            }
+        }
+
+        let game = match game {
+            Some(game) => game,
+            None => {
+                let (cols, rows) = size.unwrap_or(variant.default_size());
+                Game::new(Rules::from_parts(cols, rows, connect, variant, players, scoring)
+                    .map_err(|e| RecordError { line: rules_line, kind: RecordErrorKind::Rules(e) })?)
+            }
+        };
+        record.rules = game.rules();
+
+        // A result contradicting the moves is an error, a missing one is filled in
+        let actual = result_string(game.outcome(), record.rules.players);
+        for (recorded, line) in [(result, result_line), (record.header("Result"), header_line)] {
+            if let Some(recorded) = recorded {
+                if recorded != actual {
+                    return Err(RecordError {
+                        line,
+                        kind: RecordErrorKind::ResultMismatch { recorded: recorded.to_owned(), actual: actual.clone() }
+                    });
+                }
+            }
+        }
+        record.set_header("Result", &actual);
+
+        Ok(record)
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.headers {
+            writeln!(f, "[{key} \"{}\"]", value.replace('"', "'"))?;
+        }
+        writeln!(f)?;
+
+        let mut line = String::new();
+        for (i, pair) in self.moves.chunks(self.rules.players).enumerate() {
+            let mut turn = format!("{}.", i + 1);
+            for mv in pair {
+                turn += &format!(" {mv}");
+            }
+            if !line.is_empty() && line.len() + turn.len() + 1 > 80 {
+                writeln!(f, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &turn;
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += self.header("Result").unwrap_or("*");
+        writeln!(f, "{line}")
+    }
+}
+
+// Each player's score in turn order, or "*" while the game is in progress
+pub fn result_string(outcome: &GameOutcome, players: usize) -> String {
+    let scores = match outcome {
+        GameOutcome::InProgress => return "*".to_owned(),
+        GameOutcome::Win(winner, _) => (0..players).map(|id| if id == winner.id() { "1" } else { "0" }.to_owned()).collect(),
+        // Everyone else shares the win
+        GameOutcome::Loss(loser, _) => {
+            let share = if players == 2 { "1".to_owned() } else { format!("1/{}", players - 1) };
+            (0..players).map(|id| if id == loser.id() { "0".to_owned() } else { share.clone() }).collect()
+        },
+        GameOutcome::Draw => vec![format!("1/{players}"); players]
+    };
+    scores.join("-")
+}
+
+fn is_result(token: &str) -> bool {
+    let score = |s: &str| matches!(s, "0" | "1") || s.strip_prefix("1/").is_some_and(|n| n.parse::<usize>().is_ok());
+    token == "*" || (token.contains('-') && token.split('-').all(score))
+}
+
+// `Key "Value"]`, with the opening bracket already stripped
+fn parse_header(s: &str) -> Option<(&str, &str)> {
+    let s = s.strip_suffix(']')?;
+    let (key, value) = s.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    (!key.is_empty()).then_some((key, value))
+}
+
+// Current UTC date as YYYY.MM.DD
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::game::{Tile, Variant};
+
+    fn parse(text: &str) -> Result<GameRecord, RecordError> {
+        text.parse()
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut game = Game::new(Rules::STANDARD.with_variant(Variant::PopOut).unwrap());
+        for col in [3, 4, 4, 3, 5] {
+            game.play(col).unwrap();
+        }
+        game.play_move(Move::Pop(4)).unwrap();
+        let record = GameRecord::from_game(&game, &["Alice", "Bob \"B\""]);
+        let path = env::temp_dir().join(format!("c4-record-test-{}.c4", process::id()));
+        record.save(&path).unwrap();
+        let loaded = GameRecord::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.rules(), game.rules());
+        assert_eq!(loaded.moves(), game.history());
+        assert_eq!(loaded.header("Red"), Some("Alice"));
+        assert_eq!(loaded.header("Yellow"), Some("Bob 'B'"));
+        assert_eq!(loaded.header("Result"), Some("*"));
+        assert_eq!(loaded.to_game().grid(), game.grid());
+    }
+
+    #[test]
+    fn moves_and_result() {
+        let record = parse("[Red \"A\"]\n\n1. 4 4 2. 3 3 3. 2 2\n4. 1 1-0\n").unwrap();
+        assert_eq!(record.moves().len(), 7);
+        assert_eq!(record.to_game().win(), Some(Tile::RED));
+        // A missing result is filled in from the moves
+        assert_eq!(record.header("Result"), Some("1-0"));
+    }
+
+    #[test]
+    fn errors_give_the_line_and_move() {
+        let err = parse("[Variant \"Standard\"]\n\n1. 4 4 2. 9 3\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(matches!(err.kind, RecordErrorKind::IllegalMove { number: 3, mv: Move::Drop(8), error: MoveError::OutOfBounds(8) }));
+        assert_eq!(err.to_string(), "line 3: move 3 (9): column 9 is out of bounds");
+
+        let err = parse("1. 1 1 2. 1 1 3. 1 1 4. 1\n").unwrap_err();
+        assert!(matches!(err.kind, RecordErrorKind::IllegalMove { number: 7, error: MoveError::ColumnFull(0), .. }));
+
+        let err = parse("1. 4 p4\n").unwrap_err();
+        assert!(matches!(err.kind, RecordErrorKind::IllegalMove { number: 2, error: MoveError::Variant(Variant::Standard), .. }));
+
+        let err = parse("[Size \"7x6\"]\n1. 4 x\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(matches!(err.kind, RecordErrorKind::BadToken(ref token) if token == "x"));
+
+        let err = parse("[Variant \"Sideways\"]\n").unwrap_err();
+        assert!(matches!(err.kind, RecordErrorKind::UnknownVariant(_)));
+        let err = parse("[Size \"40x6\"]\n1. 4\n").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(matches!(err.kind, RecordErrorKind::Rules(RulesError::BoardSize(40, 6))));
+    }
+
+    #[test]
+    fn results_must_match_the_moves() {
+        let err = parse("[Result \"0-1\"]\n\n1. 1 2 2. 1 2 3. 1 2 4. 1\n").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(matches!(err.kind, RecordErrorKind::ResultMismatch { ref recorded, ref actual } if recorded == "0-1" && actual == "1-0"));
+
+        let err = parse("1. 4 4 1/2-1/2\n").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(matches!(err.kind, RecordErrorKind::ResultMismatch { .. }));
+        assert!(parse("1. 4 4 *\n").is_ok());
+    }
+
+    #[test]
+    fn size_defaults_to_the_variant() {
+        let record = parse("[Variant \"GravityFlip\"]\n\n1. 4 cw\n").unwrap();
+        assert_eq!(record.rules(), Rules::GRAVITY_FLIP);
+        let record = parse("[Variant \"ScoreFour\"]\n").unwrap();
+        assert_eq!(record.rules(), Rules::SCORE_FOUR);
+        let record = parse("[Variant \"PopOut\"]\n").unwrap();
+        assert_eq!((record.rules().cols, record.rules().rows), (7, 6));
+    }
+
+    #[test]
+    fn headers_only_keep_the_rules() {
+        let record: GameRecord = "[Scoring \"Misere\"]\n[Size \"8x7\"]\n".parse().unwrap();
+        assert_eq!(record.rules(), Rules { cols: 8, rows: 7, scoring: Scoring::Misere, ..Rules::STANDARD });
+        assert_eq!(record.to_game().rules().scoring, Scoring::Misere);
+    }
+
+    #[test]
+    fn misere_round_trip() {
+        let mut game = Game::new(Rules::STANDARD.with_scoring(Scoring::Misere));
+        for col in [0, 0, 1, 1, 2, 2, 3] {
+            game.play(col).unwrap();
+        }
+        let text = GameRecord::from_game(&game, &["Alice", "Bob"]).to_string();
+        let record: GameRecord = text.parse().unwrap();
+        assert_eq!(record.header("Result"), Some("0-1"));
+        assert_eq!(*record.to_game().outcome(), *game.outcome());
+    }
+
+    #[test]
+    fn results_for_more_players() {
+        assert_eq!(result_string(&GameOutcome::InProgress, 3), "*");
+        assert_eq!(result_string(&GameOutcome::Win(Tile::GREEN, vec![]), 3), "0-0-1");
+        assert_eq!(result_string(&GameOutcome::Win(Tile::BLUE, vec![]), 4), "0-0-0-1");
+        assert_eq!(result_string(&GameOutcome::Loss(Tile::YELLOW, vec![]), 3), "1/2-0-1/2");
+        assert_eq!(result_string(&GameOutcome::Loss(Tile::RED, vec![]), 4), "0-1/3-1/3-1/3");
+        assert_eq!(result_string(&GameOutcome::Draw, 3), "1/3-1/3-1/3");
+        assert_eq!(result_string(&GameOutcome::Loss(Tile::RED, vec![]), 2), "0-1");
+
+        let record = parse("[Players \"3\"]\n\n1. 1 2 7 2. 2 1 7 3. 3 4 7 4. 4 3 7 0-0-1\n").unwrap();
+        assert_eq!(record.to_game().win(), Some(Tile::GREEN));
+        let err = parse("[Players \"3\"]\n\n1. 1 2 7 2. 2 1 7 3. 3 4 7 4. 4 3 7 1-0-0\n").unwrap_err();
+        assert!(matches!(err.kind, RecordErrorKind::ResultMismatch { ref actual, .. } if actual == "0-0-1"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use c4::game::Game;
+
+const MIN_INTERVAL: Duration = Duration::from_millis(125);
+const MAX_INTERVAL: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayCommand {
+    Forward,
+    Back,
+    Start,
+    End,
+    ToggleAutoplay,
+    Faster,
+    Slower
+}
+
+// Steps through a finished or recorded game using its undo/redo history
+#[derive(Debug)]
+pub struct Replay {
+    // Moves played when the replay started; moves undone before then stay undone
+    end: usize,
+    autoplay: bool,
+    interval: Duration,
+    last_step: Instant
+}
+
+impl Replay {
+    // Rewinds `game` to the start
+    pub fn new(game: &mut Game) -> Self {
+        let end = game.history().len();
+        while game.undo().is_some() {}
+        Self {
+            end,
+            autoplay: false,
+            interval: Duration::from_secs(1),
+            last_step: Instant::now()
+        }
+    }
+
+    // Fast-forwards `game` back to its last move
+    pub fn finish(mut self, game: &mut Game) {
+        self.jump_to_end(game);
+    }
+
+    fn can_forward(&self, game: &Game) -> bool {
+        game.history().len() < self.end && game.can_redo()
+    }
+
+    pub fn forward(&mut self, game: &mut Game) {
+        if self.can_forward(game) {
+            game.redo();
+        }
+        self.last_step = Instant::now();
+    }
+
+    pub fn back(&mut self, game: &mut Game) {
+        game.undo();
+        self.last_step = Instant::now();
+    }
+
+    pub fn jump_to_start(&mut self, game: &mut Game) {
+        while game.undo().is_some() {}
+        self.autoplay = false;
+    }
+
+    pub fn jump_to_end(&mut self, game: &mut Game) {
+        while self.can_forward(game) {
+            game.redo();
+        }
+        self.autoplay = false;
+    }
+
+    pub fn toggle_autoplay(&mut self) {
+        self.autoplay = !self.autoplay;
+        self.last_step = Instant::now();
+    }
+
+    pub fn faster(&mut self) {
+        self.interval = (self.interval / 2).max(MIN_INTERVAL);
+    }
+
+    pub fn slower(&mut self) {
+        self.interval = (self.interval * 2).min(MAX_INTERVAL);
+    }
+
+    pub fn command(&mut self, cmd: ReplayCommand, game: &mut Game) {
+        match cmd {
+            ReplayCommand::Forward => self.forward(game),
+            ReplayCommand::Back => self.back(game),
+            ReplayCommand::Start => self.jump_to_start(game),
+            ReplayCommand::End => self.jump_to_end(game),
+            ReplayCommand::ToggleAutoplay => self.toggle_autoplay(),
+            ReplayCommand::Faster => self.faster(),
+            ReplayCommand::Slower => self.slower()
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Called every frame
+    pub fn update(&mut self, game: &mut Game) {
+        if !self.autoplay || self.last_step.elapsed() < self.interval {
+            return;
+        }
+        if self.can_forward(game) {
+            self.forward(game);
+        } else {
+            self.autoplay = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undone_moves_stay_undone() {
+        let mut game = Game::default();
+        for col in [0, 1, 2, 3] {
+            game.play(col).unwrap();
+        }
+        game.undo();
+        game.undo();
+
+        let mut replay = Replay::new(&mut game);
+        assert!(game.history().is_empty());
+        replay.jump_to_end(&mut game);
+        assert_eq!(game.history().len(), 2);
+        replay.forward(&mut game);
+        assert_eq!(game.history().len(), 2);
+        replay.jump_to_start(&mut game);
+        replay.finish(&mut game);
+        assert_eq!(game.history().len(), 2);
+        // The moves undone before the replay can still be redone afterwards
+        assert!(game.can_redo());
+    }
+}
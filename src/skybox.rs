@@ -63,7 +63,7 @@ impl Skybox {
         px.append(&mut pz);
         px.append(&mut nz);
 
-        let tex = dev.create_texture_with_data(&q, &TextureDescriptor {
+        let tex = dev.create_texture_with_data(q, &TextureDescriptor {
             label: None,
             size: Extent3d {
                 width: px_w,
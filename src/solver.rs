@@ -0,0 +1,345 @@
+// Perfect-play solver
+//
+// Scores follow the usual convention: a positive score means the player to
+// move wins, and the faster the win the higher the score. A score of
+// 22 - n means the winner drops their last tile as their n-th tile of the
+// game; 0 is a draw.
+
+use std::sync::Arc;
+
+use crate::{ai::COLUMN_ORDER, bitboard::{column_mask, Position, HEIGHT, WIDTH}, book::Book};
+
+const SIZE: i32 = (HEIGHT * WIDTH) as i32;
+pub const MIN_SCORE: i32 = -SIZE / 2 + 3;
+pub const MAX_SCORE: i32 = (SIZE + 1) / 2 - 3;
+
+// Prime, so that the 32 low bits of a key together with its slot identify it
+const TABLE_SIZE: usize = 8388593;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evaluation {
+    // The player to move wins with their n-th tile from now
+    Win(u32),
+    Draw,
+    // The opponent wins with their n-th tile from now
+    Loss(u32)
+}
+
+impl Evaluation {
+    pub fn from_score(pos: &Position, score: i32) -> Self {
+        let moves = pos.moves() as i32;
+        if score > 0 {
+            Evaluation::Win((SIZE / 2 + 1 - score - moves / 2) as u32)
+        } else if score < 0 {
+            Evaluation::Loss((SIZE / 2 + 1 + score - (moves + 1) / 2) as u32)
+        } else {
+            Evaluation::Draw
+        }
+    }
+}
+
+// Upper bounds on scores, keyed by position
+#[derive(Debug)]
+struct TranspositionTable {
+    keys: Vec<u32>,
+    values: Vec<i8>
+}
+
+impl TranspositionTable {
+    fn new() -> Self {
+        Self {
+            keys: vec![0; TABLE_SIZE],
+            values: vec![0; TABLE_SIZE]
+        }
+    }
+
+    fn clear(&mut self) {
+        self.keys.fill(0);
+        self.values.fill(0);
+    }
+
+    fn put(&mut self, key: u64, value: i8) {
+        let slot = key as usize % TABLE_SIZE;
+        self.keys[slot] = key as u32;
+        self.values[slot] = value;
+    }
+
+    fn get(&self, key: u64) -> Option<i8> {
+        let slot = key as usize % TABLE_SIZE;
+        (self.values[slot] != 0 && self.keys[slot] == key as u32).then_some(self.values[slot])
+    }
+}
+
+#[derive(Debug)]
+pub struct Solver {
+    tt: TranspositionTable,
+    book: Option<Arc<Book>>,
+    nodes: u64
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self { tt: TranspositionTable::new(), book: None, nodes: 0 }
+    }
+
+    pub fn with_book(book: Arc<Book>) -> Self {
+        Self { book: Some(book), ..Self::new() }
+    }
+
+    pub fn set_book(&mut self, book: Option<Arc<Book>>) {
+        self.book = book;
+    }
+
+    pub fn reset(&mut self) {
+        self.tt.clear();
+        self.nodes = 0;
+    }
+
+    // Positions explored since the last reset
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    pub fn solve(&mut self, pos: &Position) -> i32 {
+        if pos.last_move_won() {
+            // The opponent already has four in a row
+            return -(SIZE + 2 - pos.moves() as i32) / 2;
+        }
+        if pos.is_full() {
+            return 0;
+        }
+        if pos.can_win_next() {
+            return (SIZE + 1 - pos.moves() as i32) / 2;
+        }
+        if let Some(score) = self.book.as_ref().and_then(|book| book.get(pos)) {
+            return score;
+        }
+
+        // Narrow the score window with null-window searches
+        let mut min = -(SIZE - pos.moves() as i32) / 2;
+        let mut max = (SIZE + 1 - pos.moves() as i32) / 2;
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+            let score = self.negamax(pos, med, med + 1);
+            if score <= med {
+                max = score;
+            } else {
+                min = score;
+            }
+        }
+        min
+    }
+
+    // Exact score of each column for the player to move, None if the column is full
+    // or the game is already won
+    pub fn analyze(&mut self, pos: &Position) -> [Option<i32>; WIDTH] {
+        let symmetric = *pos == pos.mirror();
+        let mut scores = [None; WIDTH];
+        if pos.last_move_won() {
+            return scores;
+        }
+        for col in 0..WIDTH {
+            if !pos.can_play(col) {
+                continue;
+            }
+            if symmetric && col > (WIDTH - 1) / 2 {
+                scores[col] = scores[WIDTH - 1 - col];
+                continue;
+            }
+            scores[col] = Some(if pos.is_winning_move(col) {
+                (SIZE + 1 - pos.moves() as i32) / 2
+            } else {
+                let mut next = *pos;
+                next.play(col);
+                -self.solve(&next)
+            });
+        }
+        scores
+    }
+
+    fn negamax(&mut self, pos: &Position, mut alpha: i32, mut beta: i32) -> i32 {
+        self.nodes += 1;
+
+        let next = pos.non_losing_moves();
+        if next == 0 {
+            return -(SIZE - pos.moves() as i32) / 2;
+        }
+        if pos.moves() as i32 >= SIZE - 2 {
+            return 0;
+        }
+        if let Some(score) = self.book.as_ref().and_then(|book| book.get(pos)) {
+            return score;
+        }
+
+        // The opponent cannot win with their next tile
+        let min = -(SIZE - 2 - pos.moves() as i32) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        // We cannot win with our next tile
+        let mut max = (SIZE - 1 - pos.moves() as i32) / 2;
+        // A position and its mirror image share a score, so they share an entry
+        let key = pos.canonical_key();
+        if let Some(value) = self.tt.get(key) {
+            max = value as i32 + MIN_SCORE - 1;
+        }
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        // Try moves that create the most threats first, centre-first on ties
+        let mut moves = [(0u64, 0u32); WIDTH];
+        let mut len = 0;
+        for col in COLUMN_ORDER.into_iter().rev() {
+            let mv = next & column_mask(col);
+            if mv != 0 {
+                let score = pos.move_score(mv);
+                let mut i = len;
+                while i > 0 && moves[i - 1].1 > score {
+                    moves[i] = moves[i - 1];
+                    i -= 1;
+                }
+                moves[i] = (mv, score);
+                len += 1;
+            }
+        }
+
+        for &(mv, _) in moves[..len].iter().rev() {
+            let mut child = *pos;
+            child.play_mask(mv);
+            let score = -self.negamax(&child, -beta, -alpha);
+            if score >= beta {
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        self.tt.put(key, (alpha - MIN_SCORE + 1) as i8);
+        alpha
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn position(moves: &str) -> Position {
+        let mut pos = Position::new();
+        for c in moves.chars() {
+            pos.play(c.to_digit(10).unwrap() as usize - 1);
+        }
+        pos
+    }
+
+    // Plain alpha-beta over every move, sharing nothing with the solver but the scoring
+    fn minimax(pos: &Position, mut alpha: i32, beta: i32) -> i32 {
+        if (0..WIDTH).any(|col| pos.can_play(col) && pos.is_winning_move(col)) {
+            return (SIZE + 1 - pos.moves() as i32) / 2;
+        }
+        if pos.is_full() {
+            return 0;
+        }
+        let mut best = -SIZE;
+        for col in (0..WIDTH).filter(|&col| pos.can_play(col)) {
+            let mut next = *pos;
+            next.play(col);
+            best = best.max(-minimax(&next, -beta, -alpha));
+            alpha = alpha.max(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn matches_minimax() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut solver = Solver::new();
+        let mut checked = 0;
+        while checked < 60 {
+            let mut pos = Position::new();
+            while pos.moves() < 28 && !pos.last_move_won() {
+                let col = rng.gen_range(0..WIDTH);
+                if pos.can_play(col) {
+                    pos.play(col);
+                }
+            }
+            if pos.last_move_won() {
+                continue;
+            }
+            let expected = minimax(&pos, -SIZE, SIZE);
+            assert_eq!(solver.solve(&pos), expected);
+            let scores = solver.analyze(&pos);
+            for (col, &actual) in scores.iter().enumerate() {
+                let score = pos.can_play(col).then(|| {
+                    let mut next = pos;
+                    next.play(col);
+                    if next.last_move_won() { (SIZE + 1 - pos.moves() as i32) / 2 } else { -minimax(&next, -SIZE, SIZE) }
+                });
+                assert_eq!(actual, score);
+            }
+            assert_eq!(scores.into_iter().flatten().max(), Some(expected));
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn known_scores() {
+        let mut solver = Solver::new();
+        // Red can complete the bottom row straight away
+        let pos = position("112233");
+        assert_eq!(solver.solve(&pos), 18);
+        assert_eq!(Evaluation::from_score(&pos, 18), Evaluation::Win(1));
+        // Red threatens both ends of the bottom row
+        let pos = position("27374");
+        assert_eq!(solver.solve(&pos), -18);
+        assert_eq!(Evaluation::from_score(&pos, -18), Evaluation::Loss(1));
+        assert_eq!(solver.analyze(&pos).into_iter().flatten().max(), Some(-18));
+        // From the first test set of the usual Connect 4 solver benchmarks
+        let pos = position("2252576253462244111563365343671351441");
+        assert_eq!(solver.solve(&pos), -1);
+        assert_eq!(Evaluation::from_score(&pos, -1), Evaluation::Loss(2));
+    }
+
+    #[test]
+    fn evaluation_from_score() {
+        let pos = Position::new();
+        assert_eq!(Evaluation::from_score(&pos, MAX_SCORE), Evaluation::Win(4));
+        assert_eq!(Evaluation::from_score(&pos, 1), Evaluation::Win(21));
+        assert_eq!(Evaluation::from_score(&pos, 0), Evaluation::Draw);
+        assert_eq!(Evaluation::from_score(&pos, MIN_SCORE), Evaluation::Loss(4));
+        let pos = position("4");
+        assert_eq!(Evaluation::from_score(&pos, -1), Evaluation::Loss(20));
+        assert_eq!(Evaluation::from_score(&pos, 2), Evaluation::Win(20));
+    }
+
+    #[test]
+    fn won_positions_have_no_moves() {
+        let pos = position("1212121");
+        assert!(pos.last_move_won());
+        assert_eq!(Solver::new().analyze(&pos), [None; WIDTH]);
+    }
+}
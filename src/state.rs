@@ -1,10 +1,12 @@
-use std::{iter, sync::Arc};
+use std::{fmt, iter, path::PathBuf, sync::Arc, time::Duration};
 
 use pollster::FutureExt;
 use wgpu::*;
 use winit::{dpi::{PhysicalPosition, PhysicalSize}, window::Window};
 
-use crate::{board::Board, camera::Camera, skybox::Skybox};
+use c4::{ai::Difficulty, bitboard::Position, book::Book, engine::{Engine, Limits, Score}, game::{Direction, Game, GameOutcome, MAX_PLAYERS, Move, Power, Rotation, Rules, Scoring, Tile, Variant}, player::{Computer, Human, Player, PlayerError, Remote, Scripted}, record::GameRecord, solver::Evaluation};
+
+use crate::{Options, PlayerKind, analysis::Analysis, board::{Board, Shape, View}, camera::Camera, editor::Editor, replay::{Replay, ReplayCommand}, skybox::Skybox};
 
 #[derive(Debug)]
 pub struct State {
@@ -19,13 +21,42 @@ pub struct State {
     sky: Skybox,
     cam: Camera,
     bd: Board,
+    game: Game,
+    // Who plays each colour, and what they were set up from
+    seats: Vec<PlayerKind>,
+    players: Vec<Box<dyn Player>>,
+    difficulty: Difficulty,
+    book: Option<Arc<Book>>,
+    movetime: Duration,
+    record_path: PathBuf,
+    // Rules for new games
+    rules: Rules,
+    // Set while stepping through a game instead of playing it
+    replay: Option<Replay>,
+    // Set while setting up a position by hand
+    editor: Option<Editor>,
+    // Whether the game was played on from an edited position, which records cannot hold
+    edited: bool,
+    // Player looking at a fog-of-war game, and whether the board is hidden until the
+    // next player has the device
+    viewer: Tile,
+    handover: bool,
+    // Draw wrap-around boards as a cylinder
+    cylinder_view: bool,
+    // Set while the evaluation of each column is shown, with the last one printed
+    analysis: Option<Analysis>,
+    hovered_hint: Option<(usize, Score)>,
+    // Power disc to drop with the next click
+    selected_power: Option<Power>,
+    // Column and possible targets of a bomb waiting for the player to pick one
+    pending_bomb: Option<(usize, Vec<Direction>, usize)>,
     last_mouse: Option<PhysicalPosition<f64>>,
     pub horiz_right: bool,
     pub horiz_left: bool
 }
 
 impl State {
-    pub fn new(win: Window) -> Self {
+    pub fn new(win: Window, opts: &Options) -> Self {
         let win = Arc::new(win);
         let sz = win.inner_size();
 
@@ -79,16 +110,96 @@ impl State {
         let aspect = sz.width as f32 / sz.height as f32;
 
         let sky = Skybox::new(&dev, &q, cfg.format);
-        let cam = Camera::new(&dev, aspect);
-        let bd = Board::new(&dev, &q, cfg.format, cam.bind_group_layout());
+        let mut cam = Camera::new(&dev, aspect);
+        cam.fit_board(opts.rules.cols, opts.rules.rows);
+        let mut bd = Board::new(&dev, &q, cfg.format, cam.bind_group_layout(), opts.rules.cols, opts.rules.rows);
+        for (id, &color) in opts.colors.iter().enumerate() {
+            bd.set_color(Tile::new(id), color);
+        }
 
         win.set_visible(true);
 
-        Self {
+        let mut state = Self {
             win, sfc, dev, q, sky, cam, cfg, bd, depth_cfg, depth, depth_view,
+            game: Game::new(opts.rules),
+            seats: opts.seats.clone(),
+            players: Vec::new(),
+            difficulty: Difficulty::Medium,
+            book: opts.book.clone(),
+            movetime: opts.movetime,
+            record_path: opts.record_path.clone(),
+            rules: opts.rules,
+            replay: None,
+            editor: None,
+            edited: false,
+            viewer: Tile::RED,
+            handover: false,
+            cylinder_view: false,
+            analysis: None,
+            hovered_hint: None,
+            selected_power: None,
+            pending_bomb: None,
             horiz_right: false,
             horiz_left: false,
             last_mouse: None
+        };
+        state.players = (0..MAX_PLAYERS).map(|id| state.build_player(Tile::new(id))).collect();
+        state
+    }
+
+    // Sets up whoever the seat says plays this colour, or a person here if a remote
+    // player or engine cannot be started
+    fn build_player(&self, tile: Tile) -> Box<dyn Player> {
+        let player: Result<Box<dyn Player>, PlayerError> = match &self.seats[tile.id()] {
+            PlayerKind::Human => return Box::new(Human::new()),
+            PlayerKind::Computer => return Box::new(Computer::new(self.difficulty, self.book.clone())),
+            PlayerKind::Script(moves) => return Box::new(Scripted::new(moves.iter().copied())),
+            PlayerKind::Connect(addr) => {
+                println!("Connecting to {addr} to play {tile}");
+                Remote::connect(addr.as_str(), tile).map(|remote| Box::new(remote) as _).map_err(Into::into)
+            },
+            PlayerKind::Listen(port) => {
+                println!("Waiting on port {port} for someone to play {tile}");
+                Remote::listen(*port, tile).map(|remote| Box::new(remote) as _).map_err(Into::into)
+            },
+            PlayerKind::Engine(command) => {
+                println!("Starting {} to play {tile}", command.join(" "));
+                let limits = Limits { movetime: Some(self.movetime), ..Limits::default() };
+                Engine::spawn(&command[0], &command[1..], limits).map(|engine| Box::new(engine) as _).map_err(Into::into)
+            }
+        };
+        match player {
+            Ok(player) => player,
+            Err(e) => {
+                println!("No player for {tile}: {e}");
+                Box::new(Human::new())
+            }
+        }
+    }
+
+    fn set_seat(&mut self, tile: Tile, kind: PlayerKind) {
+        self.seats[tile.id()] = kind;
+        self.players[tile.id()] = self.build_player(tile);
+    }
+
+    fn is_human(&self, tile: Tile) -> bool {
+        self.players[tile.id()].is_human()
+    }
+
+    // Games with someone elsewhere have to stay in step with their copy
+    fn is_shared(&self) -> bool {
+        if self.players.iter().any(|player| player.is_remote()) {
+            println!("Not while playing a remote player");
+            return true;
+        }
+        false
+    }
+
+    // Passes a move picked with the mouse or keyboard to the person whose turn it is
+    fn input(&mut self, mv: Move) {
+        let player = &mut self.players[self.game.current_player().id()];
+        if player.is_human() {
+            player.input(mv);
         }
     }
 
@@ -114,22 +225,444 @@ impl State {
         }
     }
 
+    // The mouse position in NDC
+    fn mouse_ndc(&self) -> Option<(f32, f32)> {
+        let pos = self.last_mouse?;
+        let sz = self.win.inner_size();
+        Some((pos.x as f32 / sz.width as f32 * 2. - 1., 1. - pos.y as f32 / sz.height as f32 * 2.))
+    }
+
     fn update_preview(&mut self) {
-        if let Some(pos) = self.last_mouse {
-            let sz = self.win.inner_size();
-            let x = pos.x as f32 / sz.width as f32 * 2. - 1.;
-            let y = 1. - pos.y as f32 / sz.height as f32 * 2.;
+        if self.replay.is_some() || self.editor.is_some() || self.handover {
+            self.bd.hide_preview();
+        } else if let Some((x, y)) = self.mouse_ndc() {
             self.bd.set_preview(x, y, &mut self.cam);
         }
     }
 
+    // The cell under the mouse
+    fn hovered_cell(&mut self) -> Option<(usize, usize)> {
+        let (x, y) = self.mouse_ndc()?;
+        self.bd.cell_from_ndc(x, y, &mut self.cam)
+    }
+
     pub fn mouse_move(&mut self, pos: PhysicalPosition<f64>) {
         self.last_mouse = Some(pos);
     }
 
     pub fn mouse_click(&mut self) {
+        if self.handover {
+            self.handover = false;
+            self.viewer = self.game.current_player();
+            return;
+        }
+        if self.editor.is_some() {
+            let players = self.game.rules().players;
+            if let Some(((row, col), editor)) = self.hovered_cell().zip(self.editor.as_mut()) {
+                editor.cycle_cell(row, col, players);
+            }
+            return;
+        }
+        if self.replay.is_some() || !self.is_human(self.game.current_player()) {
+            return;
+        }
+
         self.update_preview();
-        self.bd.drop_tile();
+        if !self.game.rules().has_gravity() {
+            if let Some((row, col)) = self.bd.preview_cell() {
+                self.input(Move::Place(row, col));
+            }
+            return;
+        }
+        let Some(col) = self.bd.preview() else {
+            return;
+        };
+        match self.selected_power {
+            None => self.input(Move::Drop(col)),
+            Some(Power::Bomb) => self.aim_bomb(col),
+            Some(power) => self.input(Move::Power(power, col))
+        }
+    }
+
+    // Toggles which power disc the next click drops
+    pub fn select_power(&mut self, power: Power) {
+        self.pending_bomb = None;
+        if self.selected_power == Some(power) {
+            self.selected_power = None;
+            println!("Dropping ordinary discs");
+        } else if self.game.rules().variant != Variant::PowerUp {
+            println!("Power discs are only available in {}", Variant::PowerUp);
+        } else if !self.game.has_power(self.game.current_player(), power) {
+            println!("The {power} disc has already been used");
+        } else {
+            self.selected_power = Some(power);
+            println!("Dropping the {power} disc");
+        }
+    }
+
+    fn aim_bomb(&mut self, col: usize) {
+        let targets: Vec<_> = Direction::ALL.into_iter()
+            .filter(|&dir| self.game.is_legal(Move::Bomb(col, dir)))
+            .collect();
+        match targets.len() {
+            0 => println!("A bomb in column {} has nothing to hit", col + 1),
+            1 => self.input(Move::Bomb(col, targets[0])),
+            _ => {
+                println!("Choose the bomb's target with Tab, then press Enter");
+                self.pending_bomb = Some((col, targets, 0));
+            }
+        }
+    }
+
+    pub fn cycle_bomb_target(&mut self) {
+        if let Some((_, targets, index)) = &mut self.pending_bomb {
+            *index = (*index + 1) % targets.len();
+        }
+    }
+
+    pub fn confirm_bomb(&mut self) {
+        if let Some((col, targets, index)) = self.pending_bomb.take() {
+            self.input(Move::Bomb(col, targets[index]));
+        }
+    }
+
+    // Leaves the editor without changing the game, or else drops a pending bomb
+    pub fn cancel(&mut self) {
+        if self.editor.take().is_some() {
+            println!("Editing cancelled");
+        } else {
+            self.pending_bomb = None;
+        }
+    }
+
+    // Starts setting up a position, or plays on from the one set up if it is legal
+    pub fn toggle_editor(&mut self) {
+        if self.replay.is_some() {
+            println!("Turn replay off before editing");
+            return;
+        }
+        if self.editor.is_none() && self.is_shared() {
+            return;
+        }
+        match &self.editor {
+            Some(editor) => match editor.finish(self.game.rules()) {
+                Ok(game) => {
+                    println!("Playing on with {} to move", editor.to_move());
+                    self.game = game;
+                    self.editor = None;
+                    self.edited = true;
+                },
+                Err(e) => println!("Cannot play on from this position: {e}")
+            },
+            // Editing would show every hidden disc
+            None if self.game.rules().variant == Variant::FogOfWar && !self.game.is_over() => {
+                println!("Fog of war games cannot be edited until they are over");
+            },
+            None => {
+                self.editor = Some(Editor::new(&self.game));
+                self.pending_bomb = None;
+                self.selected_power = None;
+                println!("Click cells to change them, T to change who moves, P to play on or Escape to cancel");
+            }
+        }
+    }
+
+    pub fn cycle_to_move(&mut self) {
+        let players = self.game.rules().players;
+        if let Some(editor) = &mut self.editor {
+            editor.cycle_to_move(players);
+            println!("{} to move", editor.to_move());
+        }
+    }
+
+    // Pops the current player's tile from the bottom of the column under the cursor
+    pub fn mouse_right_click(&mut self) {
+        if self.replay.is_some() || self.editor.is_some() || self.handover || !self.is_human(self.game.current_player()) {
+            return;
+        }
+
+        self.update_preview();
+        if let Some(col) = self.bd.preview() {
+            self.input(Move::Pop(col));
+        }
+    }
+
+    // Turns the board in Gravity Flip instead of dropping a disc
+    pub fn rotate_board(&mut self, rotation: Rotation) {
+        if self.replay.is_some() || self.editor.is_some() || self.handover || !self.is_human(self.game.current_player()) {
+            return;
+        }
+        self.input(Move::Rotate(rotation));
+    }
+
+    // Cycles the computer between playing each player, last to first, and nobody
+    pub fn cycle_ai_player(&mut self) {
+        let players = self.game.rules().players;
+        let computer = (0..players).find(|&id| self.seats[id] == PlayerKind::Computer);
+        let next = match computer {
+            None => Some(players - 1),
+            Some(0) => None,
+            Some(id) => Some(id - 1)
+        };
+        for id in 0..MAX_PLAYERS {
+            if self.seats[id] == PlayerKind::Computer {
+                self.set_seat(Tile::new(id), PlayerKind::Human);
+            }
+        }
+        match next {
+            Some(id) => {
+                self.set_seat(Tile::new(id), PlayerKind::Computer);
+                println!("Computer plays {}", Tile::new(id));
+            },
+            None => println!("Computer is off")
+        }
+    }
+
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty;
+        for tile in (0..MAX_PLAYERS).map(Tile::new) {
+            if self.seats[tile.id()] == PlayerKind::Computer {
+                self.set_seat(tile, PlayerKind::Computer);
+            }
+        }
+        println!("Difficulty: {difficulty:?}");
+    }
+
+    pub fn toggle_cylinder_view(&mut self) {
+        if !self.game.rules().wraps() {
+            println!("Only {} boards can be drawn as a cylinder", Variant::Cylinder);
+            return;
+        }
+        self.cylinder_view = !self.cylinder_view;
+    }
+
+    pub fn toggle_hints(&mut self) {
+        if self.analysis.take().is_some() {
+            println!("Hints off");
+            return;
+        }
+        if let Err(e) = Position::try_from(&self.game) {
+            println!("No hints for this game: {e}");
+            return;
+        }
+        self.analysis = Some(Analysis::new(self.book.clone()));
+        self.hovered_hint = None;
+        println!("Hints on: each large block above a column is five moves to the end and each small one is one");
+    }
+
+    pub fn undo(&mut self) {
+        if self.replay.is_some() || self.editor.is_some() || self.is_shared() || self.game.undo().is_none() {
+            return;
+        }
+        // Take back the computer's reply as well, so it is the human's turn again
+        if !self.is_human(self.game.current_player()) && self.game.can_undo() {
+            self.game.undo();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if self.replay.is_some() || self.editor.is_some() || self.is_shared() || self.game.redo().is_none() {
+            return;
+        }
+        if !self.is_human(self.game.current_player()) && self.game.can_redo() {
+            self.game.redo();
+        }
+    }
+
+    pub fn new_game(&mut self) {
+        if self.is_shared() {
+            return;
+        }
+        self.replay = None;
+        self.editor = None;
+        self.edited = false;
+        self.viewer = Tile::RED;
+        self.handover = false;
+        self.game = Game::new(self.rules);
+        let rules = self.rules;
+        println!(
+            "New {} {}x{} connect {} game for {} players with {} scoring",
+            rules.variant, rules.cols, rules.rows, rules.connect, rules.players, rules.scoring
+        );
+    }
+
+    fn player_name(&self, tile: Tile) -> String {
+        self.players[tile.id()].name()
+    }
+
+    pub fn save_game(&self) {
+        if self.edited {
+            println!("Games played on from an edited position cannot be saved");
+            return;
+        }
+        let names: Vec<_> = self.game.rules().turn_order().map(|tile| self.player_name(tile)).collect();
+        let names: Vec<_> = names.iter().map(String::as_str).collect();
+        let record = GameRecord::from_game(&self.game, &names);
+        match record.save(&self.record_path) {
+            Ok(()) => println!("Saved game to {}", self.record_path.display()),
+            Err(e) => println!("Failed to save {}: {e}", self.record_path.display())
+        }
+    }
+
+    pub fn load_game(&mut self) {
+        if self.is_shared() {
+            return;
+        }
+        match GameRecord::load(&self.record_path) {
+            Ok(record) => {
+                self.replay = None;
+                self.editor = None;
+                self.edited = false;
+                self.handover = false;
+                self.game = record.to_game();
+                println!("Loaded game from {}", self.record_path.display());
+            },
+            Err(e) => println!("Failed to load {}: {e}", self.record_path.display())
+        }
+    }
+
+    pub fn toggle_replay(&mut self) {
+        if self.editor.is_some() || self.is_shared() {
+            return;
+        }
+        match self.replay.take() {
+            Some(replay) => {
+                replay.finish(&mut self.game);
+                println!("Replay off");
+            },
+            None => {
+                self.replay = Some(Replay::new(&mut self.game));
+                println!("Replay on");
+            }
+        }
+    }
+
+    pub fn replay_command(&mut self, cmd: ReplayCommand) {
+        if let Some(replay) = &mut self.replay {
+            replay.command(cmd, &mut self.game);
+            if matches!(cmd, ReplayCommand::Faster | ReplayCommand::Slower) {
+                println!("Autoplay every {:?}", replay.interval());
+            }
+        }
+    }
+
+    fn play(&mut self, mv: Move) {
+        self.pending_bomb = None;
+        if mv.power().is_some() {
+            self.selected_power = None;
+        }
+        if let Err(e) = self.game.play_move(mv) {
+            println!("{e}");
+            return;
+        }
+        for tile in self.game.rules().turn_order() {
+            if let Err(e) = self.players[tile.id()].move_played(&self.game, mv) {
+                self.replace_player(tile, e);
+            }
+        }
+        if self.game.is_over() && self.game.rules().scoring == Scoring::CountFours {
+            let scores: Vec<_> = self.game.rules().turn_order()
+                .zip(self.game.scores())
+                .map(|(tile, score)| format!("{tile} {score}"))
+                .collect();
+            println!("Lines: {}", scores.join(", "));
+        }
+        match self.game.outcome() {
+            GameOutcome::Win(tile, _) => println!("{tile} wins!"),
+            GameOutcome::Loss(tile, _) => println!("{tile} completed a line and loses!"),
+            GameOutcome::Draw => println!("Draw!"),
+            GameOutcome::InProgress => {}
+        }
+    }
+
+    // What the board shows of a fog-of-war game, passing the device between people playing
+    // at the same screen whenever it becomes another one's turn
+    fn update_view(&mut self) -> View {
+        let fog = self.game.rules().variant == Variant::FogOfWar;
+        if !fog || self.game.is_over() {
+            self.handover = false;
+            return View::All;
+        }
+        // Only people here look at the board
+        if !self.is_human(self.viewer) {
+            if let Some(tile) = self.game.rules().turn_order().find(|&tile| self.is_human(tile)) {
+                self.viewer = tile;
+            }
+        }
+        // Replays show what the viewer could see, without passing the device around
+        let current = self.game.current_player();
+        if self.replay.is_none() && self.is_human(current) && current != self.viewer {
+            if !self.handover {
+                println!("Pass the device to {current}, then click to continue");
+                self.handover = true;
+            }
+            return View::Nothing;
+        }
+        self.handover = false;
+        View::Player(self.viewer)
+    }
+
+    // Asks whoever's turn it is for a move, handing their colour to a person here if they
+    // cannot carry on
+    fn update_players(&mut self) {
+        if self.replay.is_some() || self.editor.is_some() || self.game.is_over() {
+            return;
+        }
+
+        let tile = self.game.current_player();
+        let mv = match self.players[tile.id()].poll_move(&self.game) {
+            Ok(Some(mv)) => mv,
+            Ok(None) => return,
+            Err(e) => {
+                self.replace_player(tile, e);
+                return;
+            }
+        };
+        if let Err(e) = self.game.check_move(mv) {
+            if !self.is_human(tile) {
+                self.replace_player(tile, format!("{mv} is not a legal move ({e})"));
+                return;
+            }
+        }
+        if let Some(info) = self.players[tile.id()].info() {
+            println!("{} plays {mv}: {info}", self.player_name(tile));
+        }
+        self.play(mv);
+    }
+
+    fn replace_player(&mut self, tile: Tile, reason: impl fmt::Display) {
+        println!("{} cannot play {tile}: {reason}", self.player_name(tile));
+        self.set_seat(tile, PlayerKind::Human);
+        println!("{tile} is now played here");
+    }
+
+    // Scores of the columns for the overlay, printing the one under the mouse whenever it
+    // changes
+    fn update_hints(&mut self) -> Vec<Option<Score>> {
+        let Some(analysis) = &mut self.analysis else {
+            return Vec::new();
+        };
+        if self.editor.is_some() || self.game.is_over() {
+            return Vec::new();
+        }
+        let Some(scores) = Position::try_from(&self.game).ok().and_then(|pos| analysis.scores(&pos)) else {
+            return Vec::new();
+        };
+
+        let hovered = self.bd.preview().and_then(|col| Some((col, scores.get(col).copied().flatten()?)));
+        if hovered != self.hovered_hint {
+            if let Some((col, score)) = hovered {
+                let text = match score {
+                    Score::Exact(Evaluation::Win(n)) => format!("win in {n}"),
+                    Score::Exact(Evaluation::Draw) => "draw".to_owned(),
+                    Score::Exact(Evaluation::Loss(n)) => format!("loss in {n}"),
+                    Score::Heuristic(score) => format!("about {score:+}")
+                };
+                println!("Column {}: {text}", col + 1);
+            }
+            self.hovered_hint = hovered;
+        }
+        scores.to_vec()
     }
 
     pub fn render(&mut self) {
@@ -138,10 +671,45 @@ impl State {
         self.cam.add_angle(angle_delta);
 
         self.update_preview();
+        self.update_players();
+        if let Some(replay) = &mut self.replay {
+            replay.update(&mut self.game);
+        }
+
+        let rules = self.game.rules();
+        let shape = match rules.peg_side() {
+            Some(side) => Shape::Pegs(side),
+            None if self.cylinder_view && rules.wraps() => Shape::Cylinder,
+            None => Shape::Flat
+        };
+        if self.bd.set_shape(&self.dev, rules.cols, rules.rows, shape) {
+            match shape {
+                Shape::Pegs(side) => self.cam.fit_pegs(side, rules.rows),
+                _ => self.cam.fit_board(rules.cols, rules.rows)
+            }
+        }
+
+        // Undo, new games and loading can make the selection stale
+        if self.selected_power.is_some_and(|power| !self.game.has_power(self.game.current_player(), power)) {
+            self.selected_power = None;
+        }
+        if self.pending_bomb.as_ref().is_some_and(|(col, targets, index)| !self.game.is_legal(Move::Bomb(*col, targets[*index]))) {
+            self.pending_bomb = None;
+        }
+        self.bd.set_preview_power(self.selected_power);
+        let target = match &self.editor {
+            Some(_) => self.hovered_cell(),
+            None => self.pending_bomb.as_ref().and_then(|(col, targets, index)| self.game.bomb_target(*col, targets[*index]))
+        };
+        self.bd.set_highlight(target);
+        let view = self.update_view();
+        self.bd.set_view(view);
+        let hints = self.update_hints();
+        self.bd.set_hints(hints);
 
         self.sky.prepare(&self.q, &mut self.cam);
         let camerabg = self.cam.bind_group(&self.q);
-        self.bd.prepare(&self.q);
+        self.bd.prepare(&self.q, &self.game, self.editor.as_ref().map(Editor::grid));
 
         let tex = self.sfc.get_current_texture().unwrap();
 